@@ -1 +1,277 @@
+//! Runs ClickHouse migrations from a service or a test.
+//!
+//! ```no_run
+//! use migrator::Migrator;
+//!
+//! async fn startup() -> migrator::Result<()> {
+//!     let report = Migrator::new()
+//!         .uri("http://localhost:8123/?database=app")
+//!         .migrations_dir("./migrations")
+//!         .run_latest()
+//!         .await?;
+//!
+//!     println!("{}", report);
+//!     Ok(())
+//! }
+//! ```
+//!
+//! Settings can also come from `clickhouse.toml` and `CLICKHOUSE_MIGRATE_*`
+//! environment variables, see [`Migrator::load`].
 
+pub use migrator_core;
+pub use migrator_core::clients::clickhouse::DatabaseClient;
+pub use migrator_core::clients::config::Config;
+pub use migrator_core::clients::driver::{Driver, DriverType};
+pub use migrator_core::embed_migrations;
+pub use migrator_core::error::ErrorType;
+pub use migrator_core::migration::{Migration, MigrationFile};
+pub use migrator_core::plan::{Plan, PlanStep};
+pub use migrator_core::report::{ExecutionReport, MigrationState, MigrationStatus, StatusReport};
+pub use migrator_core::result::Result;
+
+use migrator_core::clients::config::LayeredConfig;
+use migrator_core::reader;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Builds a [`Driver`] from a [`Config`] and the migrations to run with it.
+#[derive(Clone)]
+pub struct Migrator {
+    config: Config,
+    migrations: Option<Vec<MigrationFile>>,
+    code_migrations: Vec<Arc<dyn Migration>>,
+    lock_wait: Option<Duration>,
+    client: Option<Box<dyn DatabaseClient>>,
+}
+
+impl std::default::Default for Migrator {
+    fn default() -> Self {
+        Migrator::new()
+    }
+}
+
+impl Migrator {
+    /// Starts from an empty config for the HTTP driver, which connects to
+    /// `http://localhost:8123`.
+    pub fn new() -> Migrator {
+        let mut config = Config::default();
+        config.uri = None;
+
+        Migrator::from_config(config)
+    }
+
+    pub fn from_config(config: Config) -> Migrator {
+        Migrator {
+            config,
+            migrations: None,
+            code_migrations: Vec::new(),
+            lock_wait: None,
+            client: None,
+        }
+    }
+
+    /// Starts from an empty config and runs queries with `client` instead of
+    /// connecting, e.g. with a `FakeClient` in tests.
+    pub fn with_client(client: Box<dyn DatabaseClient>) -> Migrator {
+        Migrator::new().client(client)
+    }
+
+    /// Reads the config file at `path`, if there is one, with
+    /// `CLICKHOUSE_MIGRATE_*` environment variables layered over it.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Migrator> {
+        let config = LayeredConfig::load(path.as_ref())?.env_vars()?;
+
+        Ok(Migrator::from_config(config.into_config()))
+    }
+
+    /// Applies the overrides of a named environment from the config file.
+    pub fn environment(self, name: &str) -> Result<Migrator> {
+        Ok(Migrator {
+            config: self.config.environment(name)?,
+            ..self
+        })
+    }
+
+    pub fn driver(mut self, driver: DriverType) -> Migrator {
+        self.config.driver = driver;
+        self
+    }
+
+    pub fn uri(self, uri: &str) -> Migrator {
+        self.map_config(|config| config.uri(uri))
+    }
+
+    pub fn host(self, host: &str) -> Migrator {
+        self.map_config(|config| config.host(host))
+    }
+
+    pub fn port(self, port: u16) -> Migrator {
+        self.map_config(|config| config.db_port(&i32::from(port)))
+    }
+
+    pub fn user(self, user: &str) -> Migrator {
+        self.map_config(|config| config.user_name(user))
+    }
+
+    pub fn password(self, password: &str) -> Migrator {
+        self.map_config(|config| config.db_pass(password))
+    }
+
+    pub fn database(self, database: &str) -> Migrator {
+        self.map_config(|config| config.db_database(database))
+    }
+
+    pub fn secure(self, secure: bool) -> Migrator {
+        self.map_config(|config| config.secure(secure))
+    }
+
+    pub fn cluster(self, cluster: &str) -> Migrator {
+        self.map_config(|config| config.cluster(cluster))
+    }
+
+    /// Sets a value for `${name}` placeholders in migrations.
+    pub fn variable(self, name: &str, value: &str) -> Migrator {
+        self.map_config(|config| config.variable(name, value))
+    }
+
+    /// Sets how long the lock is held without a heartbeat before another
+    /// run can take it over.
+    pub fn lock_ttl(self, ttl: Duration) -> Migrator {
+        self.map_config(|config| config.lock_ttl(&ttl.as_secs()))
+    }
+
+    /// Waits up to `wait` for another run to release the lock instead of
+    /// failing straight away.
+    pub fn lock_wait(mut self, wait: Duration) -> Migrator {
+        self.lock_wait = Some(wait);
+        self
+    }
+
+    /// Reads migrations from `path` when running, the directory is created
+    /// if it doesn't exist.
+    pub fn migrations_dir<P: AsRef<Path>>(mut self, path: P) -> Migrator {
+        self.config.migrations = Some(path.as_ref().display().to_string());
+        self.migrations = None;
+        self
+    }
+
+    /// Only reads migrations whose path under the migrations directory
+    /// matches one of the included globs, e.g. `2024/**`.
+    pub fn include(self, pattern: &str) -> Migrator {
+        self.map_config(|config| config.migrations_include(pattern))
+    }
+
+    /// Skips migrations whose path under the migrations directory matches
+    /// the glob, e.g. `**/drafts/**`.
+    pub fn exclude(self, pattern: &str) -> Migrator {
+        self.map_config(|config| config.migrations_exclude(pattern))
+    }
+
+    /// Runs the given migrations instead of reading them from a directory.
+    pub fn migrations(mut self, migrations: Vec<MigrationFile>) -> Migrator {
+        self.migrations = Some(migrations);
+        self
+    }
+
+    /// Registers a Rust migration, which runs in order with the others by
+    /// version.
+    pub fn migration<M: Migration + 'static>(mut self, migration: M) -> Migrator {
+        self.code_migrations.push(Arc::new(migration));
+        self
+    }
+
+    /// Runs queries with `client` instead of connecting with the config.
+    pub fn client(mut self, client: Box<dyn DatabaseClient>) -> Migrator {
+        self.client = Some(client);
+        self
+    }
+
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    /// Applies all pending migrations.
+    pub async fn run_latest(&self) -> Result<ExecutionReport> {
+        let migrations = self.load_migrations()?;
+
+        self.connect()?.migrate(migrations).await
+    }
+
+    /// Applies the next `steps` pending migrations.
+    pub async fn run_steps(&self, steps: usize) -> Result<ExecutionReport> {
+        let migrations = self.load_migrations()?;
+
+        self.connect()?.migrate_steps(migrations, steps).await
+    }
+
+    /// Applies pending migrations up to and including `target`, or rolls
+    /// back to it if it is already applied.
+    pub async fn run_to(&self, target: &str) -> Result<ExecutionReport> {
+        let migrations = self.load_migrations()?;
+
+        self.connect()?.migrate_to(migrations, target).await
+    }
+
+    /// Reverts the last `steps` applied migrations.
+    pub async fn rollback(&self, steps: usize) -> Result<ExecutionReport> {
+        let migrations = self.load_migrations()?;
+
+        self.connect()?.rollback_steps(migrations, steps).await
+    }
+
+    /// Records migrations up to and including `version` as applied without
+    /// running them, for adopting a database that was set up by hand.
+    pub async fn baseline(&self, version: &str) -> Result<ExecutionReport> {
+        let migrations = self.load_migrations()?;
+
+        self.connect()?.baseline(migrations, version).await
+    }
+
+    /// The queries `run_latest` would run, without running them.
+    pub async fn plan(&self) -> Result<Plan> {
+        let migrations = self.load_migrations()?;
+
+        self.connect()?.plan(migrations).await
+    }
+
+    pub async fn status(&self) -> Result<StatusReport> {
+        let migrations = self.load_migrations()?;
+
+        self.connect()?.status(migrations).await
+    }
+
+    /// Connects with the current settings, for anything the methods above
+    /// don't cover.
+    pub fn connect(&self) -> Result<Driver> {
+        let mut driver = match &self.client {
+            Some(client) => Driver::from_client(client.clone(), self.config.clone())?,
+            None => Driver::from_config(self.config.clone())?,
+        };
+
+        if let Some(wait) = self.lock_wait {
+            driver = driver.with_lock_wait(wait);
+        }
+
+        for migration in &self.code_migrations {
+            driver = driver.with_migration(migration.clone());
+        }
+
+        Ok(driver)
+    }
+
+    fn load_migrations(&self) -> Result<Vec<MigrationFile>> {
+        if let Some(migrations) = &self.migrations {
+            return Ok(migrations.clone());
+        }
+
+        reader::load_migrations(&self.config)
+    }
+
+    fn map_config(self, f: impl FnOnce(Config) -> Config) -> Migrator {
+        Migrator {
+            config: f(self.config),
+            ..self
+        }
+    }
+}
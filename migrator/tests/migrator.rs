@@ -0,0 +1,102 @@
+use migrator::{MigrationFile, Migrator};
+use migrator_core::testing::FakeClient;
+
+fn migration(name: &str, sql: &str) -> MigrationFile {
+    MigrationFile {
+        name: name.to_string(),
+        sql: sql.to_string(),
+        rollback: name.ends_with(".down.sql"),
+    }
+}
+
+fn migrations() -> Vec<MigrationFile> {
+    vec![
+        migration(
+            "20240101000000_users.up.sql",
+            "CREATE TABLE ${database}.users (id UInt64)",
+        ),
+        migration("20240101000000_users.down.sql", "DROP TABLE users"),
+        migration(
+            "20240102000000_events.up.sql",
+            "CREATE TABLE events (id UInt64) TTL ts + INTERVAL ${days} DAY",
+        ),
+        migration("20240102000000_events.down.sql", "DROP TABLE events"),
+    ]
+}
+
+fn migrator(client: &FakeClient) -> Migrator {
+    Migrator::with_client(Box::new(client.clone()))
+        .database("app")
+        .variable("days", "30")
+        .migrations(migrations())
+}
+
+fn applied(client: &FakeClient) -> Vec<String> {
+    client.migrations().into_iter().map(|m| m.name).collect()
+}
+
+#[tokio::test]
+async fn run_latest_applies_pending_migrations_with_the_client() {
+    let client = FakeClient::new();
+
+    let report = migrator(&client).run_latest().await.unwrap();
+
+    assert_eq!(report.migrations().len(), 2);
+    assert_eq!(
+        applied(&client),
+        vec![
+            "20240101000000_users.up.sql",
+            "20240102000000_events.up.sql"
+        ]
+    );
+    assert!(client
+        .queries()
+        .contains(&"CREATE TABLE app.users (id UInt64)".to_string()));
+    assert!(client.active_locks().is_empty());
+}
+
+#[tokio::test]
+async fn run_latest_skips_applied_migrations() {
+    let client = FakeClient::new();
+    let migrator = migrator(&client);
+
+    migrator.run_latest().await.unwrap();
+    let report = migrator.run_latest().await.unwrap();
+
+    assert!(report.is_empty());
+    assert_eq!(applied(&client).len(), 2);
+}
+
+#[tokio::test]
+async fn plan_lists_the_queries_without_running_them() {
+    let client = FakeClient::new();
+
+    let plan = migrator(&client).plan().await.unwrap();
+
+    let names: Vec<&str> = plan.steps().iter().map(|s| s.name.as_str()).collect();
+
+    assert_eq!(
+        names,
+        vec![
+            "20240101000000_users.up.sql",
+            "20240102000000_events.up.sql"
+        ]
+    );
+    assert_eq!(
+        plan.steps()[1].queries[0],
+        "CREATE TABLE events (id UInt64) TTL ts + INTERVAL 30 DAY"
+    );
+    assert!(client.tables().is_empty());
+    assert!(applied(&client).is_empty());
+    assert!(!client.queries().iter().any(|q| q.contains("CREATE TABLE")));
+}
+
+#[tokio::test]
+async fn plan_is_empty_once_everything_is_applied() {
+    let client = FakeClient::new();
+    let migrator = migrator(&client);
+
+    migrator.run_latest().await.unwrap();
+
+    assert!(migrator.plan().await.unwrap().is_empty());
+}
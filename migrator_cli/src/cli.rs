@@ -1,12 +1,70 @@
+use migrator_core::variables::parse_var;
+use std::path::PathBuf;
+use std::time::Duration;
 use structopt::StructOpt;
 
 #[derive(Debug, StructOpt)]
 #[structopt(name = "migrator", about = "Migrate your database")]
-pub enum Opts {
+pub struct Opts {
+    #[structopt(
+        long,
+        global = true,
+        help = "Environment from the config to use, e.g. staging"
+    )]
+    pub env: Option<String>,
+
+    #[structopt(
+        long = "var",
+        global = true,
+        number_of_values = 1,
+        parse(try_from_str = parse_var),
+        help = "Value for a ${name} placeholder in migrations, as name=value"
+    )]
+    pub vars: Vec<(String, String)>,
+
+    #[structopt(flatten)]
+    pub overrides: Overrides,
+
+    #[structopt(subcommand)]
+    pub command: Command,
+}
+
+// Flags that take precedence over the config file and environment variables
+#[derive(Debug, StructOpt)]
+pub struct Overrides {
+    #[structopt(
+        long,
+        global = true,
+        parse(from_os_str),
+        help = "Path to the config file, defaults to ./clickhouse.toml"
+    )]
+    pub config: Option<PathBuf>,
+
+    #[structopt(short, long, global = true, help = "Url for database")]
+    pub uri: Option<String>,
+
+    #[structopt(short, long, global = true, help = "Path to migrations")]
+    pub migrations: Option<String>,
+
+    #[structopt(long, global = true, help = "Database user")]
+    pub user: Option<String>,
+
+    #[structopt(long, global = true, help = "Database password")]
+    pub password: Option<String>,
+
+    #[structopt(long, global = true, help = "Database to migrate")]
+    pub database: Option<String>,
+}
+
+#[derive(Debug, StructOpt)]
+pub enum Command {
     // Setup the migration tool
     Setup(Setup),
 
     Migrate(Migrate),
+
+    // Inspect or release the migration lock
+    Lock(Lock),
 }
 
 #[derive(Debug, StructOpt)]
@@ -15,10 +73,25 @@ pub enum Migrate {
     Make(MigrateMake),
 
     // Migrate to latest
-    Latest,
+    Latest(MigrateLatest),
+
+    // Apply the next pending migrations
+    Up(MigrateUp),
+
+    // Rollback the latest migrations
+    Rollback(MigrateRollback),
+
+    // Migrate up or down to a specific migration
+    To(MigrateTo),
+
+    // Show the state of every migration
+    Status,
 
-    // Rollback latest migration
-    Rollback,
+    // Replace legacy checksums in the history table
+    Restamp(MigrateRestamp),
+
+    // Mark migrations as applied without running them
+    Baseline(MigrateBaseline),
 }
 
 #[derive(Debug, StructOpt)]
@@ -27,6 +100,83 @@ pub struct MigrateMake {
     pub name: String,
 }
 
+#[derive(Debug, StructOpt)]
+pub struct MigrateLatest {
+    #[structopt(long, help = "Print the queries without running them")]
+    pub dry_run: bool,
+
+    #[structopt(
+        long,
+        parse(try_from_str = humantime::parse_duration),
+        help = "How long to wait for the migration lock, e.g. 30s or 5m"
+    )]
+    pub wait_for_lock: Option<Duration>,
+}
+
+#[derive(Debug, StructOpt)]
+pub struct MigrateUp {
+    #[structopt(
+        short,
+        long,
+        default_value = "1",
+        help = "Number of migrations to apply"
+    )]
+    pub steps: usize,
+}
+
+#[derive(Debug, StructOpt)]
+pub struct MigrateRollback {
+    #[structopt(
+        short,
+        long,
+        default_value = "1",
+        help = "Number of migrations to rollback"
+    )]
+    pub steps: usize,
+
+    #[structopt(long, help = "Print the queries without running them")]
+    pub dry_run: bool,
+}
+
+#[derive(Debug, StructOpt)]
+pub struct MigrateRestamp {
+    #[structopt(long, help = "Restamp migrations whose legacy checksum doesn't match")]
+    pub force: bool,
+}
+
+#[derive(Debug, StructOpt)]
+pub struct MigrateTo {
+    #[structopt(help = "Name or timestamp prefix of the target migration")]
+    pub target: String,
+}
+
+#[derive(Debug, StructOpt)]
+pub struct MigrateBaseline {
+    #[structopt(
+        long,
+        help = "Name or timestamp prefix of the last migration that is already applied"
+    )]
+    pub version: String,
+}
+
+#[derive(Debug, StructOpt)]
+pub enum Lock {
+    // Show who holds the migration lock
+    Status,
+
+    // Release the migration lock held by another run
+    Release(LockRelease),
+}
+
+#[derive(Debug, StructOpt)]
+pub struct LockRelease {
+    #[structopt(long, help = "Release the lock even if another run holds it")]
+    pub force: bool,
+
+    #[structopt(short, long, help = "Don't ask for confirmation")]
+    pub yes: bool,
+}
+
 #[derive(Debug, StructOpt)]
 pub enum Setup {
     Init,
@@ -38,13 +188,10 @@ pub enum Setup {
 
 #[derive(Debug, StructOpt)]
 pub struct Set {
-    #[structopt(short, long, help = "Url for database")]
-    pub uri: Option<String>,
-
-    #[structopt(short, long, help = "Path to migrations")]
-    pub migrations: Option<String>,
+    #[structopt(short, long, help = "Cluster to create the bookkeeping tables on")]
+    pub cluster: Option<String>,
 }
 
 pub fn parse() -> Opts {
-    return Opts::from_args();
+    Opts::from_args()
 }
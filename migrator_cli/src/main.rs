@@ -1,115 +1,354 @@
 mod cli;
 
-use migrator_core::clients::config;
+use chrono::{Local, TimeZone};
+use migrator_core::clients::config::{self, Config, LayeredConfig, Source};
 use migrator_core::clients::driver::Driver;
-use migrator_core::{error::ErrorType, migration::MigrationFile, reader, result::Result, util};
+use migrator_core::{error::ErrorType, migration::MigrationFile, reader, result::Result};
+use std::io::Write;
+use std::path::Path;
 use tracing::*;
+use url::Url;
+
+const MASKED_PASSWORD: &str = "********";
 
 #[tokio::main]
-async fn main() -> Result<()> {
+async fn main() {
     tracing_subscriber::fmt::init();
 
-    let mut config = config::load_config().expect("unable to load config");
+    if let Err(e) = run().await {
+        eprintln!("error: {}", e);
+        std::process::exit(exit_code(&e));
+    }
+}
 
+async fn run() -> Result<()> {
     let opts: cli::Opts = cli::parse();
 
-    match opts {
-        cli::Opts::Setup(params) => match params {
+    let path = match &opts.overrides.config {
+        Some(path) => path.clone(),
+        None => config::config_path()?,
+    };
+
+    match opts.command {
+        cli::Command::Setup(params) => match params {
             cli::Setup::Init => {
-                info!("Config file has been created.");
+                if path.exists() {
+                    info!("Config file already exists.");
+                } else {
+                    Config::default().write_to(&path)?;
+                    info!("Config file has been created.");
+                }
             }
             cli::Setup::Set(set) => {
-                let mut changed = false;
+                // Only the file is edited, values from the environment or
+                // other flags are never written to it
+                let mut config = config::load_config_from(&path)?;
+                let overrides = opts.overrides;
 
-                if let Some(uri) = set.uri {
-                    config.uri = Some(uri);
-                    changed = true;
-                }
+                let changed = match &opts.env {
+                    Some(env) => {
+                        let environment = config.environments.entry(env.clone()).or_default();
 
-                if let Some(migrations) = set.migrations {
-                    config.migrations = Some(migrations);
-                    changed = true;
-                }
+                        set_option(&mut environment.uri, overrides.uri)
+                            | set_option(&mut environment.migrations, overrides.migrations)
+                            | set_option(&mut environment.cluster, set.cluster)
+                    }
+                    None => {
+                        set_option(&mut config.uri, overrides.uri)
+                            | set_option(&mut config.migrations, overrides.migrations)
+                            | set_option(&mut config.cluster, set.cluster)
+                    }
+                };
 
                 if changed {
-                    match config.write() {
-                        Ok(()) => info!("Config file has been changed"),
-                        Err(e) => panic!("writing config file: {}", e),
-                    }
+                    config.write_to(&path)?;
+                    info!("Config file has been changed")
                 } else {
-                    info!("Options are cluster-file, proto-file and mapping-file")
+                    info!("Options are uri, migrations and cluster")
                 }
             }
             cli::Setup::View => {
-                info!("{:?}", config);
+                let config = load_config(&path, &opts.env, &opts.overrides)?;
+
+                println!("{:<18} {:<40} SOURCE", "FIELD", "VALUE");
+
+                for (field, value, source) in config.fields() {
+                    let value = match value {
+                        Some(_) if field == "db_pass" => MASKED_PASSWORD.to_string(),
+                        Some(value) if field == "uri" => mask_uri_password(&value),
+                        Some(value) => value,
+                        None => "-".to_string(),
+                    };
+
+                    println!("{:<18} {:<40} {}", field, value, source);
+                }
             }
         },
-        cli::Opts::Migrate(params) => match params {
-            cli::Migrate::Make(params) => {
-                let migrations = match config.migrations {
-                    Some(migrations) => migrations,
-                    None => {
-                        return Err(ErrorType::MissingConfigDefinition(
-                            "Missing migrations definition".into(),
-                        ))
+        cli::Command::Migrate(params) => {
+            let config = load_config(&path, &opts.env, &opts.overrides)?.into_config();
+
+            match params {
+                cli::Migrate::Make(params) => {
+                    let migrations = match config.migrations {
+                        Some(migrations) => migrations,
+                        None => {
+                            return Err(ErrorType::MissingConfigDefinition(
+                                "Missing migrations definition".into(),
+                            ))
+                        }
+                    };
+
+                    MigrationFile::create(migrations, params.name)?
+                }
+                cli::Migrate::Latest(params) => {
+                    let migrations = reader::load_migrations(&config)?;
+
+                    let mut driver = driver(config, &opts.vars)?;
+
+                    if let Some(wait) = params.wait_for_lock {
+                        driver = driver.with_lock_wait(wait);
                     }
-                };
 
-                MigrationFile::create(migrations, params.name)
-                    .expect("unable to write migration file")
-            }
-            cli::Migrate::Latest => {
-                let migrations = match &config.migrations {
-                    Some(migrations) => migrations,
-                    None => {
-                        return Err(ErrorType::MissingConfigDefinition(
-                            "Missing migrations definition".into(),
-                        ))
+                    if params.dry_run {
+                        let plan = driver.plan(migrations).await?;
+
+                        println!("{}", plan);
+                        return Ok(());
                     }
-                };
 
-                let location =
-                    util::standardise_path(&migrations).expect("Unable to standardise path.");
+                    let report = driver.migrate(migrations).await?;
+
+                    info!("{}", report);
+                }
+                cli::Migrate::Up(params) => {
+                    let migrations = reader::load_migrations(&config)?;
 
-                let migrations =
-                    reader::find_migration_files(location.clone()).expect("no migrations found");
+                    let mut driver = driver(config, &opts.vars)?;
 
-                let mut driver = Driver::from_config(config);
+                    let report = driver.migrate_steps(migrations, params.steps).await?;
 
-                let report = driver
-                    .migrate(migrations)
-                    .await
-                    .expect("could not generate report");
+                    info!("{}", report);
+                }
+                cli::Migrate::Rollback(params) => {
+                    let migrations = reader::load_migrations(&config)?;
 
-                info!("{}", report);
-            }
-            cli::Migrate::Rollback => {
-                let migrations = match &config.migrations {
-                    Some(migrations) => migrations,
-                    None => {
-                        return Err(ErrorType::MissingConfigDefinition(
-                            "Missing migrations definition".into(),
-                        ))
+                    let mut driver = driver(config, &opts.vars)?;
+
+                    if params.dry_run {
+                        let plan = driver.plan_rollback(migrations, params.steps).await?;
+
+                        println!("{}", plan);
+                        return Ok(());
                     }
-                };
 
-                let location =
-                    util::standardise_path(&migrations).expect("Unable to standardise path.");
+                    let report = driver.rollback_steps(migrations, params.steps).await?;
+
+                    info!("{}", report);
+                }
+                cli::Migrate::To(params) => {
+                    let migrations = reader::load_migrations(&config)?;
+
+                    let mut driver = driver(config, &opts.vars)?;
+
+                    let report = driver.migrate_to(migrations, &params.target).await?;
+
+                    info!("{}", report);
+                }
+                cli::Migrate::Status => {
+                    let migrations = reader::load_migrations(&config)?;
+
+                    let mut driver = driver(config, &opts.vars)?;
+
+                    let report = driver.status(migrations).await?;
+
+                    println!("{}", report);
+                }
+                cli::Migrate::Restamp(params) => {
+                    let migrations = reader::load_migrations(&config)?;
+
+                    let mut driver = driver(config, &opts.vars)?;
+
+                    let report = driver.restamp(migrations, params.force).await?;
 
-                let migrations =
-                    reader::find_migration_files(location.clone()).expect("no migrations found");
+                    info!("{}", report);
+                }
+                cli::Migrate::Baseline(params) => {
+                    let migrations = reader::load_migrations(&config)?;
 
-                let mut driver = Driver::from_config(config);
+                    let mut driver = driver(config, &opts.vars)?;
 
-                let report = driver
-                    .rollback(migrations)
-                    .await
-                    .expect("could not generate report");
+                    let report = driver.baseline(migrations, &params.version).await?;
 
-                info!("{}", report);
+                    info!("{}", report);
+                }
             }
-        },
+        }
+        cli::Command::Lock(params) => {
+            let config = load_config(&path, &opts.env, &opts.overrides)?.into_config();
+
+            let mut driver = driver(config, &opts.vars)?;
+
+            // Reading the lock doesn't create the bookkeeping tables, there's
+            // no lock without them.
+            let tables = driver.tables().await?;
+
+            let holder = if tables
+                .iter()
+                .any(|t| t.name == "clickhouse_migration_locks")
+            {
+                driver.lock_status().await?
+            } else {
+                None
+            };
+
+            match params {
+                cli::Lock::Status => match holder {
+                    Some(holder) => println!(
+                        "Locked by {} on {} since {}, expires at {}",
+                        holder.owner,
+                        holder.host,
+                        format_timestamp(holder.acquired_at),
+                        format_timestamp(holder.expires_at)
+                    ),
+                    None => println!("Unlocked"),
+                },
+                cli::Lock::Release(release) => {
+                    let holder = match holder {
+                        Some(holder) => holder,
+                        None => {
+                            println!("Unlocked");
+                            return Ok(());
+                        }
+                    };
+
+                    if !release.force {
+                        return Err(ErrorType::DatabaseLocked {
+                            owner: holder.owner,
+                            host: holder.host,
+                            expires_at: holder.expires_at,
+                        });
+                    }
+
+                    let prompt = format!(
+                        "Release the lock held by {} on {} since {}?",
+                        holder.owner,
+                        holder.host,
+                        format_timestamp(holder.acquired_at)
+                    );
+
+                    if release.yes || confirm(&prompt)? {
+                        driver.force_unlock().await?;
+                        info!("Migration lock has been released");
+                    }
+                }
+            }
+        }
     }
 
     Ok(())
 }
+
+// Layers the selected environment, CLICKHOUSE_MIGRATE_* variables and flags
+// over the config file.
+fn load_config(
+    path: &Path,
+    env: &Option<String>,
+    overrides: &cli::Overrides,
+) -> Result<LayeredConfig> {
+    let mut config = LayeredConfig::load(path)?;
+
+    if let Some(env) = env {
+        config = config.environment(env)?;
+    }
+
+    config = config.env_vars()?;
+
+    let flags = [
+        ("uri", "--uri", &overrides.uri),
+        ("migrations", "--migrations", &overrides.migrations),
+        ("db_user_name", "--user", &overrides.user),
+        ("db_pass", "--password", &overrides.password),
+        ("db_database", "--database", &overrides.database),
+    ];
+
+    for (field, flag, value) in flags {
+        if let Some(value) = value {
+            config = config.set(field, value, Source::Flag(flag.to_string()))?;
+        }
+    }
+
+    Ok(config)
+}
+
+// Hides the password in `user:password@host` uris. Uris that can't be
+// parsed are hidden entirely if they could contain credentials.
+fn mask_uri_password(uri: &str) -> String {
+    match Url::parse(uri) {
+        Ok(mut url) if url.password().is_some() => match url.set_password(Some(MASKED_PASSWORD)) {
+            Ok(()) => url.to_string(),
+            Err(()) => MASKED_PASSWORD.to_string(),
+        },
+        Ok(_) => uri.to_string(),
+        Err(_) if uri.contains('@') => MASKED_PASSWORD.to_string(),
+        Err(_) => uri.to_string(),
+    }
+}
+
+fn set_option(field: &mut Option<String>, value: Option<String>) -> bool {
+    match value {
+        Some(value) => {
+            *field = Some(value);
+            true
+        }
+        None => false,
+    }
+}
+
+fn driver(config: Config, vars: &[(String, String)]) -> Result<Driver> {
+    let mut driver = Driver::from_config(config)?;
+
+    for (name, value) in vars {
+        driver = driver.with_variable(name, value);
+    }
+
+    Ok(driver)
+}
+
+fn format_timestamp(nanos: u64) -> String {
+    Local
+        .timestamp_nanos(nanos as i64)
+        .format("%Y-%m-%d %H:%M:%S")
+        .to_string()
+}
+
+fn confirm(prompt: &str) -> Result<bool> {
+    print!("{} [y/N] ", prompt);
+    std::io::stdout().flush()?;
+
+    let mut answer = String::new();
+    std::io::stdin().read_line(&mut answer)?;
+
+    Ok(matches!(answer.trim(), "y" | "Y" | "yes"))
+}
+
+fn exit_code(error: &ErrorType) -> i32 {
+    match error {
+        ErrorType::UnableToReadConfig(_)
+        | ErrorType::UnableToWriteConfig(_)
+        | ErrorType::MissingConfigDefinition(_)
+        | ErrorType::UnknownEnvironment(_)
+        | ErrorType::InvalidConfigValue { .. }
+        | ErrorType::Tls(_)
+        | ErrorType::UnsupportedNativeTls(_)
+        | ErrorType::FailedToReadCertificate(..) => 2,
+        ErrorType::DatabaseLocked { .. } | ErrorType::LockLost => 3,
+        ErrorType::ChecksumMismatch { .. } => 4,
+        ErrorType::MissingMigrationFiles(_) => 5,
+        ErrorType::EmptyMigration(_) => 6,
+        ErrorType::Clickhouse(_)
+        | ErrorType::ClickhouseNative(_)
+        | ErrorType::StatementFailed { .. }
+        | ErrorType::MigrationFailed { .. } => 7,
+        _ => 1,
+    }
+}
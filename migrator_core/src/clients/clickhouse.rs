@@ -1,36 +1,72 @@
 use crate::clients::traits::{RowFetcher, Transaction};
 use crate::result::Result;
 use async_trait::async_trait;
+use clickhouse::query::Query;
 use clickhouse::{Client as ClickHouse, Row as ClickhouseRow};
 use serde::{Deserialize, Serialize};
 
-pub trait DatabaseClient: Transaction + RowFetcher<MigrationsRow> + RowFetcher<LockRow> {}
+pub trait DatabaseClient:
+    Transaction + RowFetcher<MigrationsRow> + RowFetcher<LockRow> + RowFetcher<TableRow> + Send
+{
+    /// Another handle to the same database, used to refresh the lock while
+    /// a migration runs.
+    fn boxed_clone(&self) -> Box<dyn DatabaseClient>;
+}
+
+impl<
+        T: Transaction
+            + RowFetcher<MigrationsRow>
+            + RowFetcher<LockRow>
+            + RowFetcher<TableRow>
+            + Send
+            + Clone
+            + 'static,
+    > DatabaseClient for T
+{
+    fn boxed_clone(&self) -> Box<dyn DatabaseClient> {
+        Box::new(self.clone())
+    }
+}
 
-impl<T: Transaction + RowFetcher<MigrationsRow> + RowFetcher<LockRow>> DatabaseClient for T {}
+impl Clone for Box<dyn DatabaseClient> {
+    fn clone(&self) -> Self {
+        self.boxed_clone()
+    }
+}
 
-#[derive(Debug, ClickhouseRow, Deserialize, Serialize)]
+#[derive(Debug, Clone, ClickhouseRow, Deserialize, Serialize)]
 pub struct MigrationsRow {
     pub timestamp: u64,
     pub name: String,
     pub checksum: String,
 }
 
-#[derive(Debug, ClickhouseRow, Deserialize, Serialize)]
+#[derive(Debug, Clone, ClickhouseRow, Deserialize, Serialize)]
 pub struct LockRow {
-    pub is_locked: u8,
+    pub owner: String,
+    pub host: String,
+    pub acquired_at: u64,
+    pub expires_at: u64,
+}
+
+/// A bookkeeping table as listed in `system.tables`.
+#[derive(Debug, Clone, ClickhouseRow, Deserialize, Serialize)]
+pub struct TableRow {
+    pub name: String,
+    pub engine: String,
 }
 
 #[async_trait]
 impl Transaction for ClickHouse {
     async fn execute_many(&mut self, queries: &[&str]) -> Result<()> {
         for query in queries {
-            self.query(query).execute().await?;
+            escaped(self, query).execute().await?;
         }
         Ok(())
     }
 
     async fn execute_query(&mut self, query: &str) -> Result<()> {
-        self.query(query).execute().await?;
+        escaped(self, query).execute().await?;
         Ok(())
     }
 }
@@ -38,13 +74,13 @@ impl Transaction for ClickHouse {
 #[async_trait]
 impl RowFetcher<LockRow> for ClickHouse {
     async fn fetch_one(&mut self, query: &str) -> Result<LockRow> {
-        let row = self.query(query).fetch_one::<LockRow>().await?;
+        let row = escaped(self, query).fetch_one::<LockRow>().await?;
 
         Ok(row)
     }
 
     async fn fetch_many(&mut self, query: &str) -> Result<Vec<LockRow>> {
-        let rows = self.query(query).fetch_all::<LockRow>().await?;
+        let rows = escaped(self, query).fetch_all::<LockRow>().await?;
 
         Ok(rows)
     }
@@ -53,14 +89,35 @@ impl RowFetcher<LockRow> for ClickHouse {
 #[async_trait]
 impl RowFetcher<MigrationsRow> for ClickHouse {
     async fn fetch_one(&mut self, query: &str) -> Result<MigrationsRow> {
-        let row = self.query(query).fetch_one::<MigrationsRow>().await?;
+        let row = escaped(self, query).fetch_one::<MigrationsRow>().await?;
 
         Ok(row)
     }
 
     async fn fetch_many(&mut self, query: &str) -> Result<Vec<MigrationsRow>> {
-        let rows = self.query(query).fetch_all::<MigrationsRow>().await?;
+        let rows = escaped(self, query).fetch_all::<MigrationsRow>().await?;
 
         Ok(rows)
     }
 }
+
+#[async_trait]
+impl RowFetcher<TableRow> for ClickHouse {
+    async fn fetch_one(&mut self, query: &str) -> Result<TableRow> {
+        let row = escaped(self, query).fetch_one::<TableRow>().await?;
+
+        Ok(row)
+    }
+
+    async fn fetch_many(&mut self, query: &str) -> Result<Vec<TableRow>> {
+        let rows = escaped(self, query).fetch_all::<TableRow>().await?;
+
+        Ok(rows)
+    }
+}
+
+// The client binds arguments to `?` in queries, `??` sends a literal `?` so
+// migrations can contain them in strings and comments.
+fn escaped(client: &ClickHouse, sql: &str) -> Query {
+    client.query(&sql.replace('?', "??"))
+}
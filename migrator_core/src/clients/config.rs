@@ -1,52 +1,141 @@
+use crate::clients::connection::Connection;
 use crate::clients::driver::DriverType;
 use crate::error::ErrorType;
 use crate::result::Result;
+use crate::variables::Variables;
 use serde::{Deserialize, Serialize};
-use std::path::Path;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Formatter;
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
 
+/// Prefix of the environment variables that override config fields, e.g.
+/// `CLICKHOUSE_MIGRATE_URI`.
+pub const ENV_PREFIX: &str = "CLICKHOUSE_MIGRATE_";
+
+// Every overridable field with the suffix of its environment variable.
+const FIELDS: [(&str, &str); 17] = [
+    ("driver", "DRIVER"),
+    ("migrations", "MIGRATIONS"),
+    ("migrations_include", "MIGRATIONS_INCLUDE"),
+    ("migrations_exclude", "MIGRATIONS_EXCLUDE"),
+    ("uri", "URI"),
+    ("secure", "SECURE"),
+    ("lock_ttl", "LOCK_TTL"),
+    ("cluster", "CLUSTER"),
+    ("db_host", "HOST"),
+    ("db_port", "PORT"),
+    ("db_user_name", "USER"),
+    ("db_pass", "PASSWORD"),
+    ("db_database", "DATABASE"),
+    ("tls_ca_cert", "TLS_CA_CERT"),
+    ("tls_client_cert", "TLS_CLIENT_CERT"),
+    ("tls_client_key", "TLS_CLIENT_KEY"),
+    ("tls_skip_verify", "TLS_SKIP_VERIFY"),
+];
+
 fn config_filename() -> &'static str {
     "clickhouse.toml"
 }
 
-fn config_path() -> Result<String> {
+pub fn config_path() -> Result<PathBuf> {
     let base = std::env::current_dir()?;
 
-    Ok(format!("{}/{}", base.display(), config_filename()))
+    Ok(base.join(config_filename()))
 }
 
 pub fn load_config() -> Result<Config> {
-    let path = config_path()?;
+    load_config_from(&config_path()?)
+}
 
-    match confy::load_path(&Path::new(&path)) {
-        Ok(res) => Ok(res),
-        Err(e) => Err(ErrorType::UnableToReadConfig(e)),
+/// Reads the config file at `path`, a missing file gives the default config
+/// rather than being created.
+pub fn load_config_from(path: &Path) -> Result<Config> {
+    if !path.exists() {
+        return Ok(Config::default());
     }
+
+    confy::load_path(path).map_err(ErrorType::UnableToReadConfig)
 }
 
 #[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
 pub struct Config {
     pub driver: DriverType,
     pub migrations: Option<String>,
+    /// Globs of migration paths to read, relative to `migrations`. All files
+    /// are read when empty.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub migrations_include: Vec<String>,
+    /// Globs of migration paths to skip, relative to `migrations`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub migrations_exclude: Vec<String>,
+    pub uri: Option<String>,
+    pub secure: Option<bool>,
+    pub lock_ttl: Option<u64>,
+    pub cluster: Option<String>,
+    pub(crate) db_host: Option<String>,
+    pub(crate) db_user_name: Option<String>,
+    pub(crate) db_pass: Option<String>,
+    pub(crate) db_port: Option<i32>,
+    pub(crate) db_database: Option<String>,
+    pub tls_ca_cert: Option<String>,
+    pub tls_client_cert: Option<String>,
+    pub tls_client_key: Option<String>,
+    pub tls_skip_verify: Option<bool>,
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub variables: HashMap<String, String>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub environments: BTreeMap<String, Environment>,
+}
+
+/// A named `[environments.<name>]` section. Any field that is set overrides
+/// the base config when the environment is selected.
+#[derive(Debug, Default, PartialEq, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Environment {
+    pub driver: Option<DriverType>,
+    pub migrations: Option<String>,
+    pub migrations_include: Option<Vec<String>>,
+    pub migrations_exclude: Option<Vec<String>>,
     pub uri: Option<String>,
-    db_host: Option<String>,
-    db_user_name: Option<String>,
-    db_pass: Option<String>,
-    db_port: Option<i32>,
-    db_database: Option<String>,
+    pub secure: Option<bool>,
+    pub lock_ttl: Option<u64>,
+    pub cluster: Option<String>,
+    pub db_host: Option<String>,
+    pub db_user_name: Option<String>,
+    pub db_pass: Option<String>,
+    pub db_port: Option<i32>,
+    pub db_database: Option<String>,
+    pub tls_ca_cert: Option<String>,
+    pub tls_client_cert: Option<String>,
+    pub tls_client_key: Option<String>,
+    pub tls_skip_verify: Option<bool>,
+    #[serde(skip_serializing_if = "HashMap::is_empty")]
+    pub variables: HashMap<String, String>,
 }
 
 impl std::default::Default for Config {
     fn default() -> Self {
         Self {
             driver: DriverType::ClickHouseDriver,
-            uri: Some("http://localhost:8083".to_string()),
+            uri: None,
             migrations: None,
+            migrations_include: Vec::new(),
+            migrations_exclude: Vec::new(),
+            secure: None,
+            lock_ttl: None,
+            cluster: None,
             db_host: None,
             db_user_name: None,
             db_pass: None,
             db_port: None,
             db_database: None,
+            tls_ca_cert: None,
+            tls_client_cert: None,
+            tls_client_key: None,
+            tls_skip_verify: None,
+            variables: HashMap::new(),
+            environments: BTreeMap::new(),
         }
     }
 }
@@ -59,11 +148,22 @@ impl Config {
             driver: driver_type,
             uri: None,
             migrations: None,
+            migrations_include: Vec::new(),
+            migrations_exclude: Vec::new(),
+            secure: None,
+            lock_ttl: None,
+            cluster: None,
             db_host: None,
             db_user_name: None,
             db_pass: None,
             db_port: None,
             db_database: None,
+            tls_ca_cert: None,
+            tls_client_cert: None,
+            tls_client_key: None,
+            tls_skip_verify: None,
+            variables: HashMap::new(),
+            environments: BTreeMap::new(),
         })
     }
 
@@ -109,42 +209,333 @@ impl Config {
         }
     }
 
-    pub fn build_uri(&self) -> String {
-        if let Some(uri) = &self.uri {
-            uri.clone()
+    pub fn secure(self, secure: bool) -> Config {
+        Self {
+            secure: Some(secure),
+            ..self
+        }
+    }
+
+    /// PEM bundle of CA certificates to trust for https, in addition to the
+    /// system ones.
+    pub fn tls_ca_cert(self, path: &str) -> Config {
+        Self {
+            tls_ca_cert: Some(path.into()),
+            ..self
+        }
+    }
+
+    /// PEM certificate and PKCS#8 key to authenticate with over https.
+    pub fn tls_client_cert(self, cert_path: &str, key_path: &str) -> Config {
+        Self {
+            tls_client_cert: Some(cert_path.into()),
+            tls_client_key: Some(key_path.into()),
+            ..self
+        }
+    }
+
+    /// Accepts any certificate and host name. Only meant for local
+    /// development.
+    pub fn tls_skip_verify(self, skip_verify: bool) -> Config {
+        Self {
+            tls_skip_verify: Some(skip_verify),
+            ..self
+        }
+    }
+
+    /// Only reads migrations whose path under `migrations` matches one of
+    /// the included globs, e.g. `2024/**`.
+    pub fn migrations_include(mut self, pattern: &str) -> Config {
+        self.migrations_include.push(pattern.into());
+        self
+    }
+
+    /// Skips migrations whose path under `migrations` matches the glob,
+    /// e.g. `**/drafts/**`.
+    pub fn migrations_exclude(mut self, pattern: &str) -> Config {
+        self.migrations_exclude.push(pattern.into());
+        self
+    }
+
+    pub fn lock_ttl(self, lock_ttl: &u64) -> Config {
+        Self {
+            lock_ttl: Some(*lock_ttl),
+            ..self
+        }
+    }
+
+    pub fn cluster(self, cluster: &str) -> Config {
+        Self {
+            cluster: Some(cluster.into()),
+            ..self
+        }
+    }
+
+    pub fn variable(mut self, name: &str, value: &str) -> Config {
+        self.variables.insert(name.into(), value.into());
+        self
+    }
+
+    /// Returns the base config with the named environment's overrides
+    /// applied.
+    pub fn environment(&self, name: &str) -> Result<Config> {
+        let environment = match self.environments.get(name) {
+            Some(environment) => environment.clone(),
+            None => return Err(ErrorType::UnknownEnvironment(name.to_string())),
+        };
+
+        let mut variables = self.variables.clone();
+        variables.extend(environment.variables);
+
+        Ok(Config {
+            driver: environment.driver.unwrap_or_else(|| self.driver.clone()),
+            migrations: environment.migrations.or_else(|| self.migrations.clone()),
+            migrations_include: environment
+                .migrations_include
+                .unwrap_or_else(|| self.migrations_include.clone()),
+            migrations_exclude: environment
+                .migrations_exclude
+                .unwrap_or_else(|| self.migrations_exclude.clone()),
+            uri: environment.uri.or_else(|| self.uri.clone()),
+            secure: environment.secure.or(self.secure),
+            lock_ttl: environment.lock_ttl.or(self.lock_ttl),
+            cluster: environment.cluster.or_else(|| self.cluster.clone()),
+            db_host: environment.db_host.or_else(|| self.db_host.clone()),
+            db_user_name: environment
+                .db_user_name
+                .or_else(|| self.db_user_name.clone()),
+            db_pass: environment.db_pass.or_else(|| self.db_pass.clone()),
+            db_port: environment.db_port.or(self.db_port),
+            db_database: environment.db_database.or_else(|| self.db_database.clone()),
+            tls_ca_cert: environment.tls_ca_cert.or_else(|| self.tls_ca_cert.clone()),
+            tls_client_cert: environment
+                .tls_client_cert
+                .or_else(|| self.tls_client_cert.clone()),
+            tls_client_key: environment
+                .tls_client_key
+                .or_else(|| self.tls_client_key.clone()),
+            tls_skip_verify: environment.tls_skip_verify.or(self.tls_skip_verify),
+            variables,
+            environments: BTreeMap::new(),
+        })
+    }
+
+    /// Sets a field from its string form, as given in an environment variable
+    /// or flag.
+    pub fn set(&mut self, field: &str, value: &str) -> Result<()> {
+        let invalid = || ErrorType::InvalidConfigValue {
+            field: field.to_string(),
+            value: value.to_string(),
+        };
+
+        match field {
+            "driver" => self.driver = DriverType::from_str(value)?,
+            "migrations" => self.migrations = Some(value.into()),
+            "migrations_include" => self.migrations_include = split_list(value),
+            "migrations_exclude" => self.migrations_exclude = split_list(value),
+            "uri" => self.uri = Some(value.into()),
+            "secure" => self.secure = Some(value.parse().map_err(|_| invalid())?),
+            "lock_ttl" => self.lock_ttl = Some(value.parse().map_err(|_| invalid())?),
+            "cluster" => self.cluster = Some(value.into()),
+            "db_host" => self.db_host = Some(value.into()),
+            "db_port" => self.db_port = Some(value.parse().map_err(|_| invalid())?),
+            "db_user_name" => self.db_user_name = Some(value.into()),
+            "db_pass" => self.db_pass = Some(value.into()),
+            "db_database" => self.db_database = Some(value.into()),
+            "tls_ca_cert" => self.tls_ca_cert = Some(value.into()),
+            "tls_client_cert" => self.tls_client_cert = Some(value.into()),
+            "tls_client_key" => self.tls_client_key = Some(value.into()),
+            "tls_skip_verify" => self.tls_skip_verify = Some(value.parse().map_err(|_| invalid())?),
+            _ => return Err(invalid()),
+        }
+
+        Ok(())
+    }
+
+    /// The string form of a field, `None` if it isn't set.
+    pub fn get(&self, field: &str) -> Option<String> {
+        match field {
+            "driver" => Some(self.driver.to_string()),
+            "migrations" => self.migrations.clone(),
+            "migrations_include" => join_list(&self.migrations_include),
+            "migrations_exclude" => join_list(&self.migrations_exclude),
+            "uri" => self.uri.clone(),
+            "secure" => self.secure.map(|secure| secure.to_string()),
+            "lock_ttl" => self.lock_ttl.map(|ttl| ttl.to_string()),
+            "cluster" => self.cluster.clone(),
+            "db_host" => self.db_host.clone(),
+            "db_port" => self.db_port.map(|port| port.to_string()),
+            "db_user_name" => self.db_user_name.clone(),
+            "db_pass" => self.db_pass.clone(),
+            "db_database" => self.db_database.clone(),
+            "tls_ca_cert" => self.tls_ca_cert.clone(),
+            "tls_client_cert" => self.tls_client_cert.clone(),
+            "tls_client_key" => self.tls_client_key.clone(),
+            "tls_skip_verify" => self.tls_skip_verify.map(|skip| skip.to_string()),
+            _ => None,
+        }
+    }
+
+    pub fn database(&self) -> Option<&String> {
+        self.db_database.as_ref()
+    }
+
+    /// Resolves the `uri` and the discrete `db_*` fields into the connection
+    /// settings, see [`Connection`].
+    pub fn connection(&self) -> Result<Connection> {
+        Connection::from_config(self)
+    }
+
+    /// The values for `${name}` placeholders: `database` and `cluster` from
+    /// the connection, then the `[variables]` table, which overrides them.
+    pub fn variables_for(&self, connection: &Connection) -> Variables {
+        let mut variables = Variables::new();
+        if let Some(database) = &connection.database {
+            variables.set("database", database);
+        }
+        if let Some(cluster) = &self.cluster {
+            variables.set("cluster", cluster);
+        }
+        variables.extend(&self.variables);
+
+        variables
+    }
+
+    /// The URL that is connected to, without credentials.
+    pub fn build_uri(&self) -> Result<String> {
+        Ok(self.connection()?.url.to_string())
+    }
+
+    pub fn write(&self) -> Result<()> {
+        self.write_to(&config_path()?)
+    }
+
+    pub fn write_to(&self, path: &Path) -> Result<()> {
+        confy::store_path(path, self).map_err(ErrorType::UnableToWriteConfig)
+    }
+}
+
+// Lists are comma separated in environment variables and flags.
+fn split_list(value: &str) -> Vec<String> {
+    value
+        .split(',')
+        .map(|item| item.trim())
+        .filter(|item| !item.is_empty())
+        .map(|item| item.to_string())
+        .collect()
+}
+
+fn join_list(items: &[String]) -> Option<String> {
+    if items.is_empty() {
+        return None;
+    }
+
+    Some(items.join(","))
+}
+
+/// Where a config value was set.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Source {
+    Default,
+    File(PathBuf),
+    Environment(String),
+    Variable(String),
+    Flag(String),
+}
+
+impl std::fmt::Display for Source {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Source::Default => write!(f, "default"),
+            Source::File(path) => write!(f, "file {}", path.display()),
+            Source::Environment(name) => write!(f, "environment {}", name),
+            Source::Variable(name) => write!(f, "env {}", name),
+            Source::Flag(flag) => write!(f, "flag {}", flag),
+        }
+    }
+}
+
+/// Config built up from the file, the selected environment,
+/// `CLICKHOUSE_MIGRATE_*` variables and flags, each layer overriding the
+/// ones before it.
+#[derive(Debug, Clone)]
+pub struct LayeredConfig {
+    config: Config,
+    sources: HashMap<&'static str, Source>,
+}
+
+impl LayeredConfig {
+    pub fn load(path: &Path) -> Result<LayeredConfig> {
+        let config = load_config_from(path)?;
+
+        let source = if path.exists() {
+            Source::File(path.to_path_buf())
         } else {
-            let driver = self.driver.clone();
+            Source::Default
+        };
 
-            let mut url: String = driver.prefix().to_string() + "://";
+        let sources = FIELDS
+            .iter()
+            .filter(|(field, _)| config.get(field).is_some())
+            .map(|(field, _)| (*field, source.clone()))
+            .collect();
 
-            url = url
-                + if let Some(host) = &self.db_host {
-                    host
-                } else {
-                    "localhost"
-                };
+        Ok(LayeredConfig { config, sources })
+    }
 
-            if let Some(port) = &self.db_port {
-                url = url + &format!(":{}/", port.to_string().as_str());
-            } else {
-                url = url + &format!(":{}/", 8123.to_string().as_str());
-            }
+    pub fn environment(self, name: &str) -> Result<LayeredConfig> {
+        let config = self.config.environment(name)?;
+        let mut sources = self.sources;
 
-            if let Some(username) = &self.db_user_name {
-                url = url + "?username=" + username;
+        for (field, _) in FIELDS.iter() {
+            if config.get(field) != self.config.get(field) {
+                sources.insert(field, Source::Environment(name.to_string()));
             }
+        }
 
-            if let Some(password) = &self.db_pass {
-                url = url + "?password=" + password;
+        Ok(LayeredConfig { config, sources })
+    }
+
+    /// Applies any `CLICKHOUSE_MIGRATE_*` environment variables that are set.
+    pub fn env_vars(mut self) -> Result<LayeredConfig> {
+        for (field, suffix) in FIELDS.iter() {
+            let name = format!("{}{}", ENV_PREFIX, suffix);
+
+            if let Ok(value) = std::env::var(&name) {
+                self = self.set(field, &value, Source::Variable(name))?;
             }
+        }
+
+        Ok(self)
+    }
+
+    pub fn set(mut self, field: &str, value: &str, source: Source) -> Result<LayeredConfig> {
+        self.config.set(field, value)?;
 
-            url
+        if let Some((field, _)) = FIELDS.iter().find(|(name, _)| *name == field) {
+            self.sources.insert(field, source);
         }
+
+        Ok(self)
     }
 
-    pub fn write(&self) -> Result<()> {
-        let path = config_path()?;
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    pub fn into_config(self) -> Config {
+        self.config
+    }
+
+    /// Every field with its value and where it came from.
+    pub fn fields(&self) -> Vec<(&'static str, Option<String>, Source)> {
+        FIELDS
+            .iter()
+            .map(|(field, _)| {
+                let source = self.sources.get(field).cloned().unwrap_or(Source::Default);
 
-        confy::store_path(&Path::new(&path), self).map_err(ErrorType::UnableToWriteConfig)
+                (*field, self.config.get(field), source)
+            })
+            .collect()
     }
 }
@@ -1,35 +1,65 @@
-use crate::clients::clickhouse::{DatabaseClient, LockRow, MigrationsRow};
+use crate::clients::clickhouse::{DatabaseClient, LockRow, MigrationsRow, TableRow};
 use crate::clients::config::Config;
+use crate::clients::history::{
+    delete_sql, is_legacy_engine, restamp_sql, BACKUP_HISTORY_ON_CLUSTER_QUERY,
+    BACKUP_HISTORY_QUERY, BACKUP_TABLE, RESTORE_HISTORY_QUERY, SELECT_LEGACY_MIGRATIONS_QUERY,
+    SELECT_MIGRATIONS_QUERY,
+};
+use crate::clients::lock::{Lock, DEFAULT_LOCK_TTL, LOCK_POLL_INTERVAL};
 use crate::clients::{
-    CREATE_CLICKHOUSE_LOCK_TABLE_QUERY, CREATE_CLICKHOUSE_MIGRATIONS_TABLE_QUERY,
+    CREATE_CLICKHOUSE_LOCK_TABLE_ON_CLUSTER_QUERY, CREATE_CLICKHOUSE_LOCK_TABLE_QUERY,
+    CREATE_CLICKHOUSE_MIGRATIONS_TABLE_ON_CLUSTER_QUERY, CREATE_CLICKHOUSE_MIGRATIONS_TABLE_QUERY,
+    SELECT_TABLES_QUERY,
 };
 use crate::error::ErrorType;
-use crate::migration::MigrationFile;
-use crate::report::ExecutionReport;
+use crate::migration::{down_name, is_legacy_checksum, Migration, MigrationFile, MigrationName};
+use crate::plan::{Plan, PlanStep};
+use crate::reader::sort_migrations;
+use crate::report::{ExecutionReport, MigrationState, MigrationStatus, StatusReport};
 use crate::result::Result;
-use clickhouse::Client as ClickHouse;
+use crate::statement::split_statements;
+use crate::variables::Variables;
 use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::oneshot;
+use tokio::task::JoinHandle;
 use tracing::*;
 
 #[derive(Clone, Eq, PartialEq, Debug, Deserialize, Serialize)]
 pub enum DriverType {
+    /// The HTTP interface, on port 8123 or 8443 with TLS.
     ClickHouseDriver,
+    /// The native protocol, on port 9000 or 9440 with TLS.
+    NativeDriver,
 }
 
 impl DriverType {
     pub fn prefix(&self) -> &'static str {
         match *self {
-            DriverType::ClickHouseDriver => "tcp",
+            DriverType::ClickHouseDriver => "http",
+            DriverType::NativeDriver => "tcp",
+        }
+    }
+}
+
+impl std::fmt::Display for DriverType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match *self {
+            DriverType::ClickHouseDriver => write!(f, "http"),
+            DriverType::NativeDriver => write!(f, "native"),
         }
     }
 }
 
+// Accepts the displayed names and the ones written to `clickhouse.toml`.
 impl std::str::FromStr for DriverType {
     type Err = ErrorType;
 
     fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
         let driver = match s {
-            "clickhouse" => DriverType::ClickHouseDriver,
+            "clickhouse" | "http" | "ClickHouseDriver" => DriverType::ClickHouseDriver,
+            "native" | "tcp" | "NativeDriver" => DriverType::NativeDriver,
             _ => return Err(ErrorType::InvalidDriverType(s.to_string())),
         };
         Ok(driver)
@@ -38,145 +68,604 @@ impl std::str::FromStr for DriverType {
 
 pub struct Driver {
     client: Box<dyn DatabaseClient>,
+    lock: Lock,
+    heartbeat: Option<HeartbeatTask>,
+    cluster: Option<String>,
+    variables: Variables,
+    code_migrations: Vec<Arc<dyn Migration>>,
 }
 
 impl Driver {
     pub fn new(client: Box<dyn DatabaseClient>) -> Driver {
-        Driver { client }
+        Driver {
+            client,
+            lock: Lock::new(DEFAULT_LOCK_TTL),
+            heartbeat: None,
+            cluster: None,
+            variables: Variables::new(),
+            code_migrations: Vec::new(),
+        }
     }
 
-    pub fn from_config(config: Config) -> Driver {
-        let driver_type = config.driver.clone();
-        let uri = config.build_uri();
+    /// Connects with the settings from the config, see
+    /// [`Connection`](crate::clients::connection::Connection).
+    pub fn from_config(config: Config) -> Result<Driver> {
+        let connection = config.connection()?;
 
-        let client = match driver_type {
-            DriverType::ClickHouseDriver => Box::new(ClickHouse::default().with_url(uri)),
+        let client: Box<dyn DatabaseClient> = match config.driver {
+            DriverType::ClickHouseDriver => Box::new(connection.client()?),
+            DriverType::NativeDriver => Box::new(connection.native_client()?),
         };
 
-        Driver { client }
+        Driver::from_client(client, config)
+    }
+
+    /// Runs queries with `client` and takes everything else from the config,
+    /// such as the lock TTL, cluster and variables.
+    pub fn from_client(client: Box<dyn DatabaseClient>, config: Config) -> Result<Driver> {
+        let connection = config.connection()?;
+
+        let lock_ttl = config
+            .lock_ttl
+            .map(Duration::from_secs)
+            .unwrap_or(DEFAULT_LOCK_TTL);
+
+        let variables = config.variables_for(&connection);
+
+        let mut lock = Lock::new(lock_ttl);
+        lock.replicated = config.cluster.is_some();
+
+        Ok(Driver {
+            client,
+            lock,
+            heartbeat: None,
+            cluster: config.cluster,
+            variables,
+            code_migrations: Vec::new(),
+        })
+    }
+
+    /// Sets how long the lock is held without a heartbeat before another
+    /// run can take it over.
+    pub fn with_lock_ttl(mut self, ttl: Duration) -> Driver {
+        self.lock.ttl = ttl;
+        self
+    }
+
+    /// Sets how long to wait for another run to release the lock before
+    /// failing.
+    pub fn with_lock_wait(mut self, wait: Duration) -> Driver {
+        self.lock.wait = wait;
+        self
+    }
+
+    /// Sets how long to wait after claiming the lock for claims made at the
+    /// same time to land, before checking which one is the earliest.
+    pub fn with_lock_settle(mut self, settle: Duration) -> Driver {
+        self.lock.settle = settle;
+        self
+    }
+
+    /// Creates the bookkeeping tables `ON CLUSTER` with replicated engines,
+    /// so every node shares the same history and lock.
+    pub fn with_cluster(mut self, cluster: &str) -> Driver {
+        self.cluster = Some(cluster.into());
+        self.lock.replicated = true;
+        self.variables.set("cluster", cluster);
+        self
+    }
+
+    /// Sets a value for `${name}` placeholders in migrations.
+    pub fn with_variable(mut self, name: &str, value: &str) -> Driver {
+        self.variables.set(name, value);
+        self
+    }
+
+    /// Registers a Rust migration to run alongside the migration files
+    /// passed to `migrate`, `rollback` and friends.
+    pub fn with_migration<M: Migration + 'static>(mut self, migration: M) -> Driver {
+        self.code_migrations.push(Arc::new(migration));
+        self
     }
 
     pub async fn run_migrations(&mut self) -> Result<Vec<MigrationsRow>> {
-        let entries = self
-            .client
-            .fetch_many("SELECT * FROM clickhouse_migrations")
-            .await?;
+        let entries = self.client.fetch_many(SELECT_MIGRATIONS_QUERY).await?;
 
         Ok(entries)
     }
 
-    pub async fn lock_status(&mut self) -> Option<LockRow> {
-        match self
-            .client
-            .fetch_one("SELECT * FROM clickhouse_migration_lock LIMIT 1")
-            .await
-        {
-            Ok(row) => Some(row),
-            Err(_e) => None,
+    // Reads the history as it is, without creating or upgrading the tables.
+    // A database that hasn't been migrated yet has no history.
+    async fn read_history(&mut self) -> Result<Vec<MigrationsRow>> {
+        let tables = self.tables().await?;
+
+        match tables.iter().find(|t| t.name == "clickhouse_migrations") {
+            Some(table) if is_legacy_engine(&table.engine) => {
+                self.client.fetch_many(SELECT_LEGACY_MIGRATIONS_QUERY).await
+            }
+            Some(_) => self.run_migrations().await,
+            None => Ok(vec![]),
         }
     }
 
-    pub async fn change_lock(&mut self, status: u8) -> Result<()> {
-        self.client.execute_query(
-            &format!("ALTER TABLE clickhouse_migration_lock UPDATE is_locked = {} WHERE is_locked IS NOT NULL", status)
-        ).await
+    /// The bookkeeping tables that exist in the database, with their engines.
+    pub async fn tables(&mut self) -> Result<Vec<TableRow>> {
+        self.client.fetch_many(SELECT_TABLES_QUERY).await
     }
 
+    /// Returns the current holder of the lock, if it is held and hasn't
+    /// expired.
+    pub async fn lock_status(&mut self) -> Result<Option<LockRow>> {
+        lock_holder(self.client.as_mut(), &self.lock).await
+    }
+
+    /// Claims the migration lock, waiting up to the configured lock wait for
+    /// another run to release it. Expired locks are taken over. The lock is
+    /// refreshed in the background until it is released.
+    pub async fn acquire_lock(&mut self) -> Result<()> {
+        let started = Instant::now();
+
+        loop {
+            match self.try_acquire_lock().await {
+                Ok(()) => {
+                    self.start_heartbeat().await;
+                    return Ok(());
+                }
+                Err(ErrorType::DatabaseLocked { ref owner, .. })
+                    if started.elapsed() < self.lock.wait =>
+                {
+                    info!("Waiting for migration lock held by {}", owner);
+
+                    let remaining = self.lock.wait - started.elapsed();
+                    tokio::time::sleep(remaining.min(LOCK_POLL_INTERVAL)).await;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    async fn try_acquire_lock(&mut self) -> Result<()> {
+        if let Some(holder) = self.lock_status().await? {
+            if holder.owner != self.lock.owner {
+                return Err(locked_by(holder));
+            }
+        }
+
+        self.client.execute_query(&self.lock.claim_sql()).await?;
+
+        // Another run may have claimed the lock at the same time, the
+        // earliest claim wins and everyone else backs off. Its insert may
+        // not have landed yet, so give it a moment before looking.
+        if !self.lock.settle.is_zero() {
+            tokio::time::sleep(self.lock.settle).await;
+        }
+
+        match self.lock_status().await? {
+            Some(holder) if holder.owner == self.lock.owner => {
+                self.lock.refreshed();
+                debug!("Acquired migration lock as {}", &self.lock.owner);
+
+                Ok(())
+            }
+            holder => {
+                self.client.execute_query(&self.lock.release_sql()).await?;
+
+                match holder {
+                    Some(holder) => Err(locked_by(holder)),
+                    None => Err(ErrorType::LockLost),
+                }
+            }
+        }
+    }
+
+    /// Extends the lock's expiry, failing if it has been taken over. Does
+    /// nothing if it was refreshed recently.
+    pub async fn heartbeat(&mut self) -> Result<()> {
+        heartbeat(self.client.as_mut(), &mut self.lock).await
+    }
+
+    /// Releases the lock, failing if it was lost while it was held.
+    pub async fn release_lock(&mut self) -> Result<()> {
+        let heartbeat = self.stop_heartbeat().await;
+
+        self.client.execute_query(&self.lock.release_sql()).await?;
+        self.lock.released();
+
+        debug!("Released migration lock as {}", &self.lock.owner);
+
+        heartbeat
+    }
+
+    async fn start_heartbeat(&mut self) {
+        // The previous task is replaced, whatever it ended with.
+        let _ = self.stop_heartbeat().await;
+
+        self.heartbeat = Some(HeartbeatTask::start(
+            self.client.boxed_clone(),
+            self.lock.clone(),
+        ));
+    }
+
+    async fn stop_heartbeat(&mut self) -> Result<()> {
+        match self.heartbeat.take() {
+            Some(task) => task.stop().await,
+            None => Ok(()),
+        }
+    }
+
+    // Fails if the background heartbeat has stopped because the lock was
+    // lost, so nothing else runs without it.
+    async fn check_heartbeat(&mut self) -> Result<()> {
+        match &self.heartbeat {
+            Some(task) if task.is_finished() => match self.stop_heartbeat().await {
+                Ok(()) => Err(ErrorType::LockLost),
+                error => error,
+            },
+            _ => Ok(()),
+        }
+    }
+
+    /// Releases the lock regardless of which run holds it, returning the
+    /// holder it was taken from.
+    pub async fn force_unlock(&mut self) -> Result<Option<LockRow>> {
+        let holder = self.lock_status().await?;
+
+        if let Some(holder) = &holder {
+            self.client
+                .execute_query(&self.lock.release_owner_sql(&holder.owner))
+                .await?;
+
+            warn!("Released migration lock held by {}", &holder.owner);
+        }
+
+        Ok(holder)
+    }
+
+    /// Creates the bookkeeping tables. History kept by earlier versions is
+    /// moved to `clickhouse_migrations_backup` and copied into the new
+    /// history table. On a cluster, existing tables have to be replicated.
     pub async fn prerequisite(&mut self) -> Result<()> {
-        let mut queries = vec![
-            CREATE_CLICKHOUSE_MIGRATIONS_TABLE_QUERY,
-            CREATE_CLICKHOUSE_LOCK_TABLE_QUERY,
-        ];
+        let tables = self.tables().await?;
+
+        // `CREATE TABLE IF NOT EXISTS` would keep a table that only exists on
+        // the node we're connected to, and other nodes wouldn't see its rows.
+        if self.cluster.is_some() {
+            if let Some(table) = tables.iter().find(|t| !t.engine.starts_with("Replicated")) {
+                return Err(ErrorType::UnreplicatedTable {
+                    table: table.name.clone(),
+                    engine: table.engine.clone(),
+                });
+            }
+        }
 
-        let lock_status = self.lock_status().await;
-        if lock_status.is_none() {
-            queries.push("INSERT INTO clickhouse_migration_lock (*) VALUES (0)")
+        let legacy = tables
+            .iter()
+            .any(|t| t.name == "clickhouse_migrations" && is_legacy_engine(&t.engine));
+        let backup = legacy || tables.iter().any(|t| t.name == BACKUP_TABLE);
+
+        let mut queries = Vec::new();
+
+        if legacy {
+            warn!(
+                "Upgrading clickhouse_migrations, the old table is kept as {}",
+                BACKUP_TABLE
+            );
+
+            queries.push(match self.cluster {
+                Some(_) => self.render(BACKUP_HISTORY_ON_CLUSTER_QUERY)?,
+                None => BACKUP_HISTORY_QUERY.to_string(),
+            });
         }
 
+        match self.cluster {
+            Some(_) => {
+                queries.push(self.render(CREATE_CLICKHOUSE_MIGRATIONS_TABLE_ON_CLUSTER_QUERY)?);
+                queries.push(self.render(CREATE_CLICKHOUSE_LOCK_TABLE_ON_CLUSTER_QUERY)?);
+            }
+            None => {
+                queries.push(CREATE_CLICKHOUSE_MIGRATIONS_TABLE_QUERY.to_string());
+                queries.push(CREATE_CLICKHOUSE_LOCK_TABLE_QUERY.to_string());
+            }
+        }
+
+        if backup {
+            queries.push(RESTORE_HISTORY_QUERY.to_string());
+        }
+
+        let queries: Vec<&str> = queries.iter().map(|q| q.as_str()).collect();
         self.client.execute_many(&queries).await
     }
 
+    /// Substitutes `${name}` placeholders in migration SQL. `${database}` and
+    /// `${cluster}` come from the config, and can be overridden along with
+    /// any other variable.
+    pub fn render(&self, sql: &str) -> Result<String> {
+        self.variables.render(sql)
+    }
+
     pub async fn rollback(&mut self, migrations: Vec<MigrationFile>) -> Result<ExecutionReport> {
-        let mut run_migrations = self.run_migrations().await?;
+        self.rollback_steps(migrations, 1).await
+    }
 
-        // Sort by which was run last
-        run_migrations.sort_by(|a, b| b.timestamp.cmp(&a.timestamp));
+    /// Reverts the last `steps` applied migrations, most recent first.
+    pub async fn rollback_steps(
+        &mut self,
+        migrations: Vec<MigrationFile>,
+        steps: usize,
+    ) -> Result<ExecutionReport> {
+        let migrations = self.with_code_migrations(migrations)?;
 
-        println!("{:?}", run_migrations);
+        // Run the prerequisite functions such as creating tables etc.
+        self.prerequisite().await?;
+
+        let run_migrations = self.run_migrations().await?;
+
+        if run_migrations.is_empty() {
+            info!("No migrations to rollback");
+            return Ok(ExecutionReport::new(vec![]));
+        }
 
-        unimplemented!()
+        self.revert(&migrations, latest_applied(run_migrations, steps))
+            .await
     }
 
     pub async fn migrate(&mut self, migrations: Vec<MigrationFile>) -> Result<ExecutionReport> {
+        let migrations = self.with_code_migrations(migrations)?;
+
         // Run the prerequisite functions such as creating tables etc.
         self.prerequisite().await?;
 
-        let mut ran_migrations = Vec::new();
+        let run_migrations = self.run_migrations().await?;
+
+        let new_migrations = pending_migrations(&migrations, &run_migrations)?;
+
+        self.apply(new_migrations).await
+    }
+
+    /// Applies the next `steps` pending migrations.
+    pub async fn migrate_steps(
+        &mut self,
+        migrations: Vec<MigrationFile>,
+        steps: usize,
+    ) -> Result<ExecutionReport> {
+        let migrations = self.with_code_migrations(migrations)?;
+
+        // Run the prerequisite functions such as creating tables etc.
+        self.prerequisite().await?;
 
         let run_migrations = self.run_migrations().await?;
 
-        let mut new_migrations: Vec<&MigrationFile> = vec![];
-
-        let runnable_migrations: Vec<&MigrationFile> =
-            migrations.iter().filter(|m| !m.rollback).collect();
-        for migration in &runnable_migrations {
-            let old_migration = &run_migrations.iter().find(|m| m.name == migration.name);
-
-            // Skip if this has already been run
-            if let Some(old_migration) = old_migration {
-                // Panic if the migration directory is corrupt!
-                if migration.checksum().to_string() != old_migration.checksum {
-                    panic!(
-                        "Checksum {} != {}. Migration directory is corrupt. {:?}",
-                        migration.checksum(),
-                        old_migration.checksum,
-                        vec![&migration.name, &old_migration.name]
-                    );
+        let mut new_migrations = pending_migrations(&migrations, &run_migrations)?;
+        new_migrations.truncate(steps);
+
+        self.apply(new_migrations).await
+    }
+
+    /// Lists the queries `migrate` would run, without running them. Only the
+    /// history table is read, and nothing is created.
+    pub async fn plan(&mut self, migrations: Vec<MigrationFile>) -> Result<Plan> {
+        let migrations = self.with_code_migrations(migrations)?;
+
+        let run_migrations = self.read_history().await?;
+
+        let mut steps = Vec::new();
+        for migration in pending_migrations(&migrations, &run_migrations)? {
+            let mut queries = self.statements(migration)?;
+            queries.push(migration.to_insert_sql());
+
+            steps.push(PlanStep::new(&migration.name, queries));
+        }
+
+        Ok(Plan::new(steps))
+    }
+
+    /// Lists the queries `rollback_steps` would run, without running them.
+    /// Only the history table is read, and nothing is created.
+    pub async fn plan_rollback(
+        &mut self,
+        migrations: Vec<MigrationFile>,
+        steps: usize,
+    ) -> Result<Plan> {
+        let migrations = self.with_code_migrations(migrations)?;
+
+        let applied = latest_applied(self.read_history().await?, steps);
+
+        let mut plan_steps = Vec::new();
+        for (name, down_migration) in down_migrations(&migrations, &applied)? {
+            let mut queries = self.statements(down_migration)?;
+            queries.push(delete_sql(name));
+
+            plan_steps.push(PlanStep::new(&down_migration.name, queries));
+        }
+
+        Ok(Plan::new(plan_steps))
+    }
+
+    /// Replaces legacy checksums in the history table with SHA-256 checksums
+    /// of the migration files. Rows whose legacy checksum doesn't match the
+    /// file are skipped unless `force` is set.
+    pub async fn restamp(
+        &mut self,
+        migrations: Vec<MigrationFile>,
+        force: bool,
+    ) -> Result<ExecutionReport> {
+        let migrations = self.with_code_migrations(migrations)?;
+
+        // Run the prerequisite functions such as creating tables etc.
+        self.prerequisite().await?;
+
+        let history = self.run_migrations().await?;
+
+        let mut queries = Vec::new();
+        let mut restamped = Vec::new();
+        for row in history.iter().filter(|m| is_legacy_checksum(&m.checksum)) {
+            let migration = match migrations
+                .iter()
+                .find(|m| !m.rollback && m.name == row.name)
+            {
+                Some(migration) => migration,
+                None => {
+                    warn!("{} is missing, skipping", &row.name);
+                    continue;
                 }
+            };
 
+            if migration.legacy_checksum().to_string() != row.checksum && !force {
+                warn!("{} doesn't match its legacy checksum, skipping", &row.name);
                 continue;
             }
 
-            // Check if valid file
-            if &migration.sql == "" {
-                panic!("{}. Empty migration file.", &migration.name);
-            }
+            queries.push(restamp_sql(&row.name, &migration.checksum()));
+            restamped.push(migration.clone());
+        }
 
-            new_migrations.push(migration);
+        if restamped.is_empty() {
+            return Ok(ExecutionReport::new(restamped));
         }
 
-        // Check if any migrations are missing
-        if (runnable_migrations.len() - new_migrations.len()) != run_migrations.len() {
-            let missing_migrations: Vec<&String> = run_migrations
-                .iter()
-                .filter(|rm| {
-                    runnable_migrations
-                        .iter()
-                        .find(|om| om.name == rm.name)
-                        .is_none()
-                })
-                .map(|em| &em.name)
+        self.acquire_lock().await?;
+
+        let queries: Vec<&str> = queries.iter().map(|q| q.as_str()).collect();
+        let result = self.client.execute_many(&queries).await;
+
+        self.release_lock().await?;
+        result?;
+
+        Ok(ExecutionReport::new(restamped))
+    }
+
+    /// Reports the state of every migration in the directory and history
+    /// table without running or creating anything.
+    pub async fn status(&mut self, migrations: Vec<MigrationFile>) -> Result<StatusReport> {
+        let migrations = self.with_code_migrations(migrations)?;
+
+        let run_migrations = self.read_history().await?;
+
+        Ok(StatusReport::new(migration_statuses(
+            &migrations,
+            &run_migrations,
+        )))
+    }
+
+    /// Migrates forwards or backwards to `target`. If the target is applied,
+    /// everything applied after it is reverted. Otherwise the pending
+    /// migrations up to and including it are applied, and newer migrations
+    /// that were already applied are left in place. The target can be a full
+    /// migration name or a prefix of it, such as its timestamp.
+    pub async fn migrate_to(
+        &mut self,
+        migrations: Vec<MigrationFile>,
+        target: &str,
+    ) -> Result<ExecutionReport> {
+        let migrations = self.with_code_migrations(migrations)?;
+
+        // Run the prerequisite functions such as creating tables etc.
+        self.prerequisite().await?;
+
+        let run_migrations = self.run_migrations().await?;
+
+        let target = find_target(&migrations, target)?;
+
+        if run_migrations.iter().any(|m| m.name == target.name) {
+            // The target is behind, revert everything applied after it
+            let mut newer_migrations: Vec<MigrationsRow> = run_migrations
+                .into_iter()
+                .filter(|m| order(&m.name) > order(&target.name))
                 .collect();
 
-            panic!(
-                "Migration directory is corrupt. Missing following files: {:?}",
-                missing_migrations
-            );
+            newer_migrations.sort_by(|a, b| order(&b.name).cmp(&order(&a.name)));
+
+            self.revert(&migrations, newer_migrations).await
+        } else {
+            let new_migrations = pending_migrations(&migrations, &run_migrations)?
+                .into_iter()
+                .filter(|m| order(&m.name) <= order(&target.name))
+                .collect();
+
+            self.apply(new_migrations).await
         }
+    }
 
-        let lock_status = self.lock_status().await;
-        if let Some(status) = lock_status {
-            if status.is_locked == 1 {
-                panic!("Database is currently locked, cannot run migrations");
-            }
+    /// Records every migration up to and including `version` as applied
+    /// without running it, for databases that were set up before using
+    /// migrations. The version can be a full migration name or a prefix of
+    /// it, such as its timestamp.
+    pub async fn baseline(
+        &mut self,
+        migrations: Vec<MigrationFile>,
+        version: &str,
+    ) -> Result<ExecutionReport> {
+        let migrations = self.with_code_migrations(migrations)?;
+
+        // Run the prerequisite functions such as creating tables etc.
+        self.prerequisite().await?;
+
+        let run_migrations = self.run_migrations().await?;
+
+        let target = find_target(&migrations, version)?;
+
+        let baseline_migrations: Vec<&MigrationFile> =
+            pending_migrations(&migrations, &run_migrations)?
+                .into_iter()
+                .filter(|m| order(&m.name) <= order(&target.name))
+                .collect();
+
+        if baseline_migrations.is_empty() {
+            info!("No migrations to baseline");
+            return Ok(ExecutionReport::new(vec![]));
         }
 
-        self.change_lock(1).await?;
+        self.acquire_lock().await?;
+
+        let queries: Vec<String> = baseline_migrations
+            .iter()
+            .map(|m| m.to_insert_sql())
+            .collect();
+        let queries: Vec<&str> = queries.iter().map(|q| q.as_str()).collect();
+        let result = self.client.execute_many(&queries).await;
+
+        self.release_lock().await?;
+        result?;
+
+        Ok(ExecutionReport::new(
+            baseline_migrations.into_iter().cloned().collect(),
+        ))
+    }
+
+    // Adds the entries standing in for registered Rust migrations, failing
+    // if their versions collide with each other or the migration files.
+    fn with_code_migrations(
+        &self,
+        mut migrations: Vec<MigrationFile>,
+    ) -> Result<Vec<MigrationFile>> {
+        for migration in &self.code_migrations {
+            migrations.extend(MigrationFile::code(migration.as_ref()));
+        }
+
+        sort_migrations(migrations)
+    }
+
+    async fn apply(&mut self, migrations: Vec<&MigrationFile>) -> Result<ExecutionReport> {
+        self.acquire_lock().await?;
+
+        let result = self.apply_locked(migrations).await;
+
+        self.release_lock().await?;
+
+        result
+    }
+
+    async fn apply_locked(&mut self, migrations: Vec<&MigrationFile>) -> Result<ExecutionReport> {
+        let mut ran_migrations = Vec::new();
 
         // Create the new ones
-        for migration in new_migrations {
+        for migration in migrations {
+            self.check_heartbeat().await?;
+
+            self.execute_migration(migration).await?;
+
             self.client
-                .execute_many(&[&migration.sql, &migration.to_insert_sql()])
+                .execute_query(&migration.to_insert_sql())
                 .await?;
 
             ran_migrations.push(migration.clone());
@@ -184,8 +673,354 @@ impl Driver {
             debug!("Ran migration {}", &migration.name)
         }
 
-        self.change_lock(0).await?;
+        Ok(ExecutionReport::new(ran_migrations))
+    }
+
+    // Runs the down file of each applied migration, in the order given.
+    async fn revert(
+        &mut self,
+        migrations: &[MigrationFile],
+        applied: Vec<MigrationsRow>,
+    ) -> Result<ExecutionReport> {
+        let down_migrations = down_migrations(migrations, &applied)?;
+
+        self.acquire_lock().await?;
+
+        let result = self.revert_locked(down_migrations).await;
+
+        self.release_lock().await?;
+
+        result
+    }
+
+    async fn revert_locked(
+        &mut self,
+        down_migrations: Vec<(&String, &MigrationFile)>,
+    ) -> Result<ExecutionReport> {
+        let mut ran_migrations = Vec::new();
+
+        for (name, down_migration) in down_migrations {
+            self.check_heartbeat().await?;
+
+            self.execute_migration(down_migration).await?;
+            self.remove_migration_row(name).await?;
+
+            ran_migrations.push(down_migration.clone());
+
+            debug!("Rolled back migration {}", name);
+        }
 
         Ok(ExecutionReport::new(ran_migrations))
     }
+
+    // Runs each statement of the migration in order. ClickHouse only accepts
+    // one statement per query.
+    async fn execute_migration(&mut self, migration: &MigrationFile) -> Result<()> {
+        if migration.is_code() {
+            return self.execute_code_migration(migration).await;
+        }
+
+        let sql = self.render(&migration.sql)?;
+
+        for (index, statement) in split_statements(&sql).iter().enumerate() {
+            self.check_heartbeat().await?;
+
+            if let Err(e) = self.client.execute_query(&statement.sql).await {
+                return Err(ErrorType::StatementFailed {
+                    migration: migration.name.clone(),
+                    index: index + 1,
+                    line: statement.line,
+                    error: Box::new(e),
+                });
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn execute_code_migration(&mut self, migration: &MigrationFile) -> Result<()> {
+        let code = match self
+            .code_migrations
+            .iter()
+            .find(|m| format!("{}_{}", m.version(), m.name()) == migration.stem())
+        {
+            Some(code) => code.clone(),
+            None => {
+                return Err(ErrorType::NonExistentMigrationVersions(
+                    migration.name.clone(),
+                ))
+            }
+        };
+
+        let result = if migration.rollback {
+            code.down(self.client.as_mut()).await
+        } else {
+            code.up(self.client.as_mut()).await
+        };
+
+        result.map_err(|e| ErrorType::MigrationFailed {
+            migration: migration.name.clone(),
+            error: Box::new(e),
+        })
+    }
+
+    fn statements(&self, migration: &MigrationFile) -> Result<Vec<String>> {
+        Ok(split_statements(&self.render(&migration.sql)?)
+            .into_iter()
+            .map(|s| s.sql)
+            .collect())
+    }
+
+    async fn remove_migration_row(&mut self, name: &str) -> Result<()> {
+        self.client.execute_query(&delete_sql(name)).await
+    }
+}
+
+async fn lock_holder(client: &mut dyn DatabaseClient, lock: &Lock) -> Result<Option<LockRow>> {
+    let holders: Vec<LockRow> = client.fetch_many(&lock.select_active_sql()).await?;
+
+    Ok(holders.into_iter().next())
+}
+
+async fn heartbeat(client: &mut dyn DatabaseClient, lock: &mut Lock) -> Result<()> {
+    if !lock.needs_heartbeat() {
+        return Ok(());
+    }
+
+    match lock_holder(client, lock).await? {
+        Some(holder) if holder.owner == lock.owner => {}
+        Some(holder) => return Err(locked_by(holder)),
+        None => return Err(ErrorType::LockLost),
+    }
+
+    client.execute_query(&lock.heartbeat_sql()).await?;
+    lock.refreshed();
+
+    Ok(())
+}
+
+// Refreshes the lock from a background task while it is held, so a statement
+// that runs longer than the TTL doesn't lose it. Stops when the lock is lost.
+struct HeartbeatTask {
+    stop: oneshot::Sender<()>,
+    task: JoinHandle<Result<()>>,
+}
+
+impl HeartbeatTask {
+    fn start(mut client: Box<dyn DatabaseClient>, mut lock: Lock) -> HeartbeatTask {
+        let (stop, mut stopped) = oneshot::channel();
+
+        let task = tokio::spawn(async move {
+            loop {
+                tokio::select! {
+                    _ = &mut stopped => return Ok(()),
+                    _ = tokio::time::sleep(lock.ttl / 3) => {
+                        heartbeat(client.as_mut(), &mut lock).await?;
+                    }
+                }
+            }
+        });
+
+        HeartbeatTask { stop, task }
+    }
+
+    fn is_finished(&self) -> bool {
+        self.task.is_finished()
+    }
+
+    async fn stop(self) -> Result<()> {
+        // The task may already have stopped on its own.
+        let _ = self.stop.send(());
+
+        self.task
+            .await
+            .map_err(|e| ErrorType::Io(std::io::Error::other(e)))?
+    }
+}
+
+// Sorts migrations by version, then name. Names that can't be parsed, such as
+// rows from older versions, come first.
+fn order(name: &str) -> (Option<u64>, &str) {
+    let version = name.parse::<MigrationName>().ok().map(|n| n.version);
+
+    (version, name)
+}
+
+fn locked_by(holder: LockRow) -> ErrorType {
+    ErrorType::DatabaseLocked {
+        owner: holder.owner,
+        host: holder.host,
+        expires_at: holder.expires_at,
+    }
+}
+
+// Returns the `steps` most recently applied migrations, most recent first.
+fn latest_applied(mut run_migrations: Vec<MigrationsRow>, steps: usize) -> Vec<MigrationsRow> {
+    // Sort by which was run last
+    run_migrations.sort_by_key(|m| std::cmp::Reverse(m.timestamp));
+    run_migrations.truncate(steps);
+
+    run_migrations
+}
+
+// Pairs each applied migration with its down file.
+fn down_migrations<'a>(
+    migrations: &'a [MigrationFile],
+    applied: &'a [MigrationsRow],
+) -> Result<Vec<(&'a String, &'a MigrationFile)>> {
+    let mut down_migrations = Vec::new();
+
+    for applied_migration in applied {
+        let down_name = down_name(&applied_migration.name);
+
+        let down_migration = match migrations
+            .iter()
+            .find(|m| m.rollback && m.name == down_name)
+        {
+            Some(down_migration) => down_migration,
+            None => return Err(ErrorType::NonExistentMigrationVersions(down_name)),
+        };
+
+        // Check if valid file
+        if down_migration.is_empty() {
+            return Err(ErrorType::EmptyMigration(down_migration.name.clone()));
+        }
+
+        down_migrations.push((&applied_migration.name, down_migration));
+    }
+
+    Ok(down_migrations)
+}
+
+// Compares the migration directory against the history table.
+fn migration_statuses(
+    migrations: &[MigrationFile],
+    run_migrations: &[MigrationsRow],
+) -> Vec<MigrationStatus> {
+    let runnable_migrations: Vec<&MigrationFile> =
+        migrations.iter().filter(|m| !m.rollback).collect();
+
+    let mut statuses: Vec<MigrationStatus> = runnable_migrations
+        .iter()
+        .map(|migration| {
+            let old_migration = run_migrations.iter().find(|m| m.name == migration.name);
+
+            let state = match old_migration {
+                Some(old_migration) if is_legacy_checksum(&old_migration.checksum) => {
+                    MigrationState::LegacyChecksum {
+                        applied_at: old_migration.timestamp,
+                    }
+                }
+                Some(old_migration) if migration.checksum() != old_migration.checksum => {
+                    MigrationState::ChecksumMismatch {
+                        expected: old_migration.checksum.clone(),
+                        actual: migration.checksum(),
+                    }
+                }
+                Some(old_migration) => MigrationState::Applied {
+                    applied_at: old_migration.timestamp,
+                },
+                None if migration.is_empty() => MigrationState::Empty,
+                None => MigrationState::Pending,
+            };
+
+            MigrationStatus::new(&migration.name, state)
+        })
+        .collect();
+
+    statuses.extend(
+        run_migrations
+            .iter()
+            .filter(|rm| !runnable_migrations.iter().any(|om| om.name == rm.name))
+            .map(|rm| {
+                // Rust migrations are only known to the programs that
+                // register them, the CLI and other services don't have them.
+                let state = if rm.name.ends_with(".rs") {
+                    MigrationState::AppliedExternally {
+                        applied_at: rm.timestamp,
+                    }
+                } else {
+                    MigrationState::Missing
+                };
+
+                MigrationStatus::new(&rm.name, state)
+            }),
+    );
+
+    statuses.sort_by(|a, b| order(&a.name).cmp(&order(&b.name)));
+
+    statuses
+}
+
+// Returns the up migrations that haven't been run yet, sorted by version.
+fn pending_migrations<'a>(
+    migrations: &'a [MigrationFile],
+    run_migrations: &[MigrationsRow],
+) -> Result<Vec<&'a MigrationFile>> {
+    let statuses = migration_statuses(migrations, run_migrations);
+
+    for status in &statuses {
+        match &status.state {
+            // Fail if the migration directory is corrupt!
+            MigrationState::ChecksumMismatch { expected, actual } => {
+                return Err(ErrorType::ChecksumMismatch {
+                    name: status.name.clone(),
+                    expected: expected.clone(),
+                    actual: actual.clone(),
+                })
+            }
+            MigrationState::Empty => return Err(ErrorType::EmptyMigration(status.name.clone())),
+            _ => {}
+        }
+    }
+
+    let legacy_migrations = statuses
+        .iter()
+        .filter(|s| matches!(s.state, MigrationState::LegacyChecksum { .. }))
+        .count();
+
+    if legacy_migrations > 0 {
+        warn!(
+            "{} migrations have legacy checksums and can't be verified, run `migrate restamp` to update them",
+            legacy_migrations
+        );
+    }
+
+    // Check if any migrations are missing
+    let missing_migrations: Vec<String> = statuses
+        .iter()
+        .filter(|s| s.state == MigrationState::Missing)
+        .map(|s| s.name.clone())
+        .collect();
+
+    if !missing_migrations.is_empty() {
+        return Err(ErrorType::MissingMigrationFiles(missing_migrations));
+    }
+
+    Ok(statuses
+        .iter()
+        .filter(|s| s.state == MigrationState::Pending)
+        .filter_map(|s| migrations.iter().find(|m| !m.rollback && m.name == s.name))
+        .collect())
+}
+
+// Finds the up migration matching the given name or name prefix.
+fn find_target<'a>(migrations: &'a [MigrationFile], target: &str) -> Result<&'a MigrationFile> {
+    let candidates: Vec<&MigrationFile> = migrations
+        .iter()
+        .filter(|m| !m.rollback && m.name.starts_with(target))
+        .collect();
+
+    if let Some(exact) = candidates
+        .iter()
+        .find(|m| m.name == target || m.stem() == target)
+    {
+        return Ok(exact);
+    }
+
+    match candidates.as_slice() {
+        [migration] => Ok(migration),
+        [] => Err(ErrorType::NonExistentMigrationVersions(target.to_string())),
+        _ => Err(ErrorType::AmbiguousMigrationVersion(target.to_string())),
+    }
 }
@@ -0,0 +1,69 @@
+use crate::clients::lock::VERSION_SQL;
+use crate::util::escape;
+
+/// Every apply, rollback and restamp is a new row in `clickhouse_migrations`,
+/// the table collapses them to the latest version per name. Rolled back
+/// migrations are marked `deleted`, so history is never rewritten in place.
+pub const SELECT_MIGRATIONS_QUERY: &str = "
+SELECT timestamp, name, checksum
+FROM clickhouse_migrations FINAL
+WHERE deleted = 0
+";
+
+/// Reads history tables from earlier versions, which have no `deleted` rows.
+pub const SELECT_LEGACY_MIGRATIONS_QUERY: &str = "
+SELECT timestamp, name, checksum
+FROM clickhouse_migrations
+";
+
+/// Where the history of earlier versions, which were kept in tables that
+/// can't be updated, is moved before being copied into the current table.
+pub const BACKUP_TABLE: &str = "clickhouse_migrations_backup";
+
+pub const BACKUP_HISTORY_QUERY: &str =
+    "RENAME TABLE clickhouse_migrations TO clickhouse_migrations_backup";
+
+pub const BACKUP_HISTORY_ON_CLUSTER_QUERY: &str =
+    "RENAME TABLE clickhouse_migrations TO clickhouse_migrations_backup ON CLUSTER '${cluster}'";
+
+// Only copies into an empty table, so an interrupted upgrade can be run again.
+pub const RESTORE_HISTORY_QUERY: &str = "
+INSERT INTO clickhouse_migrations (*)
+SELECT timestamp, name, checksum, 0, 0
+FROM clickhouse_migrations_backup
+WHERE (SELECT count() FROM clickhouse_migrations) = 0
+";
+
+/// Whether a `clickhouse_migrations` table with the given engine is from an
+/// earlier version and has to be upgraded.
+pub fn is_legacy_engine(engine: &str) -> bool {
+    !engine.ends_with("ReplacingMergeTree")
+}
+
+pub fn insert_sql(name: &str, checksum: &str) -> String {
+    format!(
+        "INSERT INTO clickhouse_migrations (*) SELECT now64(9), '{}', '{}', 0, {}",
+        escape(name),
+        escape(checksum),
+        VERSION_SQL
+    )
+}
+
+pub fn delete_sql(name: &str) -> String {
+    format!(
+        "INSERT INTO clickhouse_migrations (*) SELECT timestamp, name, checksum, 1, {} \
+         FROM clickhouse_migrations FINAL WHERE name = '{}' AND deleted = 0",
+        VERSION_SQL,
+        escape(name)
+    )
+}
+
+pub fn restamp_sql(name: &str, checksum: &str) -> String {
+    format!(
+        "INSERT INTO clickhouse_migrations (*) SELECT timestamp, name, '{}', 0, {} \
+         FROM clickhouse_migrations FINAL WHERE name = '{}' AND deleted = 0",
+        escape(checksum),
+        VERSION_SQL,
+        escape(name)
+    )
+}
@@ -0,0 +1,144 @@
+use crate::util::{escape, hostname};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+pub const DEFAULT_LOCK_TTL: Duration = Duration::from_secs(300);
+
+pub const LOCK_POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+// How long a claim waits for concurrent claims to land before checking which
+// one is the earliest.
+pub const DEFAULT_LOCK_SETTLE: Duration = Duration::from_secs(1);
+
+// Concurrent claims are resolved by taking the earliest one.
+pub const SELECT_ACTIVE_LOCKS_QUERY: &str = "
+SELECT owner, host, acquired_at, expires_at
+FROM clickhouse_migration_locks FINAL
+WHERE released = 0 AND expires_at > now64(9)
+ORDER BY acquired_at, owner
+";
+
+// On a cluster every replica has to see the same claims, so claims are only
+// acknowledged once a majority of replicas have them and reads wait for the
+// replica to catch up with them.
+const QUORUM_INSERT_SETTINGS: &str = "SETTINGS insert_quorum = 'auto', insert_quorum_parallel = 0";
+
+const SEQUENTIAL_SELECT_SETTINGS: &str = "SETTINGS select_sequential_consistency = 1";
+
+/// Identifies this process when it claims the migration lock.
+///
+/// Every claim, heartbeat and release is a new row in
+/// `clickhouse_migration_locks`, the table collapses them to the latest
+/// version per owner. A claim is held until it is released or its expiry
+/// passes, after which another owner can take over.
+#[derive(Debug, Clone)]
+pub struct Lock {
+    pub owner: String,
+    pub host: String,
+    pub ttl: Duration,
+    pub wait: Duration,
+    /// How long to wait after claiming before checking the claim won.
+    pub settle: Duration,
+    /// Whether the lock table is replicated across a cluster.
+    pub replicated: bool,
+    last_heartbeat: Option<Instant>,
+}
+
+impl Lock {
+    pub fn new(ttl: Duration) -> Lock {
+        let host = hostname();
+
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_nanos())
+            .unwrap_or_default();
+
+        Lock {
+            owner: format!("{}-{}-{}", host, std::process::id(), nanos),
+            host,
+            ttl,
+            wait: Duration::from_secs(0),
+            settle: DEFAULT_LOCK_SETTLE,
+            replicated: false,
+            last_heartbeat: None,
+        }
+    }
+
+    /// Whether a third of the TTL has passed since the lock was last refreshed.
+    pub fn needs_heartbeat(&self) -> bool {
+        match self.last_heartbeat {
+            Some(last_heartbeat) => last_heartbeat.elapsed() >= self.ttl / 3,
+            None => true,
+        }
+    }
+
+    pub fn refreshed(&mut self) {
+        self.last_heartbeat = Some(Instant::now());
+    }
+
+    pub fn released(&mut self) {
+        self.last_heartbeat = None;
+    }
+
+    pub fn select_active_sql(&self) -> String {
+        if self.replicated {
+            format!(
+                "{}{}",
+                SELECT_ACTIVE_LOCKS_QUERY, SEQUENTIAL_SELECT_SETTINGS
+            )
+        } else {
+            SELECT_ACTIVE_LOCKS_QUERY.to_string()
+        }
+    }
+
+    pub fn claim_sql(&self) -> String {
+        format!(
+            "INSERT INTO clickhouse_migration_locks (*) {}SELECT '{}', '{}', now64(9), {}, 0, {}",
+            self.insert_settings(),
+            escape(&self.owner),
+            escape(&self.host),
+            self.expiry_sql(),
+            VERSION_SQL
+        )
+    }
+
+    pub fn heartbeat_sql(&self) -> String {
+        format!(
+            "INSERT INTO clickhouse_migration_locks (*) {}SELECT owner, host, acquired_at, {}, 0, {} \
+             FROM clickhouse_migration_locks FINAL WHERE owner = '{}' AND released = 0",
+            self.insert_settings(),
+            self.expiry_sql(),
+            VERSION_SQL,
+            escape(&self.owner)
+        )
+    }
+
+    pub fn release_sql(&self) -> String {
+        self.release_owner_sql(&self.owner)
+    }
+
+    /// Releases the lock held by `owner`, which may be another run.
+    pub fn release_owner_sql(&self, owner: &str) -> String {
+        format!(
+            "INSERT INTO clickhouse_migration_locks (*) {}SELECT owner, host, acquired_at, expires_at, 1, {} \
+             FROM clickhouse_migration_locks FINAL WHERE owner = '{}' AND released = 0",
+            self.insert_settings(),
+            VERSION_SQL,
+            escape(owner)
+        )
+    }
+
+    fn insert_settings(&self) -> String {
+        if self.replicated {
+            format!("{} ", QUORUM_INSERT_SETTINGS)
+        } else {
+            String::new()
+        }
+    }
+
+    fn expiry_sql(&self) -> String {
+        format!("now64(9) + toIntervalSecond({})", self.ttl.as_secs())
+    }
+}
+
+// Rows for the same owner are collapsed to the one with the highest version.
+pub const VERSION_SQL: &str = "toUInt64(toUnixTimestamp64Nano(now64(9)))";
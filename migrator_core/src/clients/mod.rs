@@ -1,20 +1,66 @@
 pub mod clickhouse;
 pub mod config;
+pub mod connection;
 pub mod driver;
+pub mod history;
+pub mod lock;
+pub mod native;
+pub mod tls;
 pub mod traits;
 
 pub const CREATE_CLICKHOUSE_MIGRATIONS_TABLE_QUERY: &str = "
 CREATE TABLE IF NOT EXISTS clickhouse_migrations (
     timestamp DateTime64(9) NOT NULL,
     name TEXT NOT NULL,
-    checksum TEXT NOT NULL
+    checksum TEXT NOT NULL,
+    deleted UInt8 NOT NULL,
+    version UInt64 NOT NULL
 )
-engine=TinyLog
+engine=ReplacingMergeTree(version)
+ORDER BY name
 ";
 
 pub const CREATE_CLICKHOUSE_LOCK_TABLE_QUERY: &str = "
-CREATE TABLE IF NOT EXISTS clickhouse_migration_lock (
-    is_locked UInt8 NOT NULL
+CREATE TABLE IF NOT EXISTS clickhouse_migration_locks (
+    owner String NOT NULL,
+    host String NOT NULL,
+    acquired_at DateTime64(9) NOT NULL,
+    expires_at DateTime64(9) NOT NULL,
+    released UInt8 NOT NULL,
+    version UInt64 NOT NULL
 )
-engine=Memory
+engine=ReplacingMergeTree(version)
+ORDER BY owner
+";
+
+pub const CREATE_CLICKHOUSE_MIGRATIONS_TABLE_ON_CLUSTER_QUERY: &str = "
+CREATE TABLE IF NOT EXISTS clickhouse_migrations ON CLUSTER '${cluster}' (
+    timestamp DateTime64(9) NOT NULL,
+    name TEXT NOT NULL,
+    checksum TEXT NOT NULL,
+    deleted UInt8 NOT NULL,
+    version UInt64 NOT NULL
+)
+engine=ReplicatedReplacingMergeTree('/clickhouse/tables/{database}/clickhouse_migration_history', '{shard}-{replica}', version)
+ORDER BY name
+";
+
+pub const CREATE_CLICKHOUSE_LOCK_TABLE_ON_CLUSTER_QUERY: &str = "
+CREATE TABLE IF NOT EXISTS clickhouse_migration_locks ON CLUSTER '${cluster}' (
+    owner String NOT NULL,
+    host String NOT NULL,
+    acquired_at DateTime64(9) NOT NULL,
+    expires_at DateTime64(9) NOT NULL,
+    released UInt8 NOT NULL,
+    version UInt64 NOT NULL
+)
+engine=ReplicatedReplacingMergeTree('/clickhouse/tables/{database}/clickhouse_migration_locks', '{shard}-{replica}', version)
+ORDER BY owner
+";
+
+pub const SELECT_TABLES_QUERY: &str = "
+SELECT name, engine
+FROM system.tables
+WHERE database = currentDatabase()
+    AND name IN ('clickhouse_migrations', 'clickhouse_migrations_backup', 'clickhouse_migration_locks')
 ";
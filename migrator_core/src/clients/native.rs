@@ -0,0 +1,132 @@
+use crate::clients::clickhouse::{LockRow, MigrationsRow, TableRow};
+use crate::clients::traits::{RowFetcher, Transaction};
+use crate::error::ErrorType;
+use crate::result::Result;
+use async_trait::async_trait;
+use chrono::DateTime;
+use chrono_tz::Tz;
+use clickhouse_rs::types::{Block, Complex};
+use clickhouse_rs::{ClientHandle, Pool};
+
+/// Client for the ClickHouse native protocol, usually on port 9000 or 9440
+/// with TLS.
+#[derive(Clone)]
+pub struct NativeClient {
+    pool: Pool,
+}
+
+impl NativeClient {
+    pub fn new(pool: Pool) -> NativeClient {
+        NativeClient { pool }
+    }
+
+    async fn handle(&self) -> Result<ClientHandle> {
+        Ok(self.pool.get_handle().await?)
+    }
+
+    async fn fetch_block(&mut self, query: &str) -> Result<Block<Complex>> {
+        let mut handle = self.handle().await?;
+
+        Ok(handle.query(query).fetch_all().await?)
+    }
+}
+
+#[async_trait]
+impl Transaction for NativeClient {
+    async fn execute_many(&mut self, queries: &[&str]) -> Result<()> {
+        let mut handle = self.handle().await?;
+
+        for query in queries {
+            handle.execute(*query).await?;
+        }
+        Ok(())
+    }
+
+    async fn execute_query(&mut self, query: &str) -> Result<()> {
+        let mut handle = self.handle().await?;
+
+        handle.execute(query).await?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl RowFetcher<LockRow> for NativeClient {
+    async fn fetch_one(&mut self, query: &str) -> Result<LockRow> {
+        first(self.fetch_many(query).await?, query)
+    }
+
+    async fn fetch_many(&mut self, query: &str) -> Result<Vec<LockRow>> {
+        let block = self.fetch_block(query).await?;
+
+        let mut rows = Vec::new();
+
+        for row in block.rows() {
+            rows.push(LockRow {
+                owner: row.get("owner")?,
+                host: row.get("host")?,
+                acquired_at: nanos(row.get("acquired_at")?),
+                expires_at: nanos(row.get("expires_at")?),
+            });
+        }
+
+        Ok(rows)
+    }
+}
+
+#[async_trait]
+impl RowFetcher<MigrationsRow> for NativeClient {
+    async fn fetch_one(&mut self, query: &str) -> Result<MigrationsRow> {
+        first(self.fetch_many(query).await?, query)
+    }
+
+    async fn fetch_many(&mut self, query: &str) -> Result<Vec<MigrationsRow>> {
+        let block = self.fetch_block(query).await?;
+
+        let mut rows = Vec::new();
+
+        for row in block.rows() {
+            rows.push(MigrationsRow {
+                timestamp: nanos(row.get("timestamp")?),
+                name: row.get("name")?,
+                checksum: row.get("checksum")?,
+            });
+        }
+
+        Ok(rows)
+    }
+}
+
+#[async_trait]
+impl RowFetcher<TableRow> for NativeClient {
+    async fn fetch_one(&mut self, query: &str) -> Result<TableRow> {
+        first(self.fetch_many(query).await?, query)
+    }
+
+    async fn fetch_many(&mut self, query: &str) -> Result<Vec<TableRow>> {
+        let block = self.fetch_block(query).await?;
+
+        let mut rows = Vec::new();
+
+        for row in block.rows() {
+            rows.push(TableRow {
+                name: row.get("name")?,
+                engine: row.get("engine")?,
+            });
+        }
+
+        Ok(rows)
+    }
+}
+
+fn first<T>(rows: Vec<T>, query: &str) -> Result<T> {
+    rows.into_iter()
+        .next()
+        .ok_or_else(|| ErrorType::RowNotFound(query.to_string()))
+}
+
+// The HTTP client reads DateTime64(9) columns as nanoseconds since the epoch,
+// rows from both clients have to match.
+fn nanos(time: DateTime<Tz>) -> u64 {
+    time.timestamp_nanos_opt().unwrap_or_default() as u64
+}
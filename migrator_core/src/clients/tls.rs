@@ -0,0 +1,141 @@
+use crate::error::ErrorType;
+use crate::result::Result;
+use clickhouse::Client as ClickHouse;
+use hyper_tls::HttpsConnector;
+use hyper_util::client::legacy::connect::HttpConnector;
+use hyper_util::client::legacy::Client;
+use hyper_util::rt::TokioExecutor;
+use native_tls::{Certificate, Identity, TlsConnector};
+use std::time::Duration;
+use tracing::*;
+
+const PEM_CERTIFICATE_START: &str = "-----BEGIN CERTIFICATE-----";
+
+// Both the same as the `clickhouse` crate's default client.
+const TCP_KEEPALIVE: Duration = Duration::from_secs(60);
+
+// ClickHouse closes idle connections after 3 seconds.
+const POOL_IDLE_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// How https connections are verified and authenticated.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TlsOptions {
+    /// PEM bundle of CA certificates to trust, in addition to the system ones.
+    pub ca_cert: Option<String>,
+    /// PEM certificate presented to the server.
+    pub client_cert: Option<String>,
+    /// PKCS#8 PEM key for `client_cert`.
+    pub client_key: Option<String>,
+    /// Accepts any certificate and host name.
+    pub skip_verify: bool,
+}
+
+impl TlsOptions {
+    pub fn connector(&self) -> Result<TlsConnector> {
+        let mut builder = TlsConnector::builder();
+
+        if let Some(path) = &self.ca_cert {
+            for pem in read_pem_certificates(path)? {
+                builder.add_root_certificate(Certificate::from_pem(&pem).map_err(ErrorType::Tls)?);
+            }
+        }
+
+        match (&self.client_cert, &self.client_key) {
+            (Some(cert), Some(key)) => {
+                let identity =
+                    Identity::from_pkcs8(&read(cert)?, &read(key)?).map_err(ErrorType::Tls)?;
+                builder.identity(identity);
+            }
+            (None, None) => {}
+            _ => {
+                return Err(ErrorType::MissingConfigDefinition(
+                    "tls_client_cert and tls_client_key have to be set together".into(),
+                ))
+            }
+        }
+
+        if self.skip_verify {
+            warn!("TLS certificate verification is disabled");
+
+            builder
+                .danger_accept_invalid_certs(true)
+                .danger_accept_invalid_hostnames(true);
+        }
+
+        builder.build().map_err(ErrorType::Tls)
+    }
+
+    /// The CA certificate for the native client to trust besides the system
+    /// ones. It takes a single CA certificate and can't send a client
+    /// certificate.
+    pub fn native_ca_certificate(&self) -> Result<Option<Certificate>> {
+        if self.client_cert.is_some() || self.client_key.is_some() {
+            return Err(ErrorType::UnsupportedNativeTls(
+                "client certificates".to_string(),
+            ));
+        }
+
+        if self.skip_verify {
+            warn!("TLS certificate verification is disabled");
+        }
+
+        let path = match &self.ca_cert {
+            Some(path) => path,
+            None => return Ok(None),
+        };
+
+        match read_pem_certificates(path)?.as_slice() {
+            [pem] => Ok(Some(Certificate::from_pem(pem).map_err(ErrorType::Tls)?)),
+            [] => Err(ErrorType::InvalidConfigValue {
+                field: "tls_ca_cert".to_string(),
+                value: path.clone(),
+            }),
+            _ => Err(ErrorType::UnsupportedNativeTls(
+                "more than one certificate in tls_ca_cert".to_string(),
+            )),
+        }
+    }
+}
+
+/// A ClickHouse client that makes https connections with `connector`.
+pub fn https_client(connector: TlsConnector) -> ClickHouse {
+    let mut http = HttpConnector::new();
+    http.set_keepalive(Some(TCP_KEEPALIVE));
+    http.enforce_http(false);
+
+    let https = HttpsConnector::from((http, connector.into()));
+
+    ClickHouse::with_http_client(
+        Client::builder(TokioExecutor::new())
+            .pool_idle_timeout(POOL_IDLE_TIMEOUT)
+            .build(https),
+    )
+}
+
+fn read(path: &str) -> Result<Vec<u8>> {
+    std::fs::read(path).map_err(|e| ErrorType::FailedToReadCertificate(e, path.to_string()))
+}
+
+/// Splits a PEM bundle into its certificates, `Certificate::from_pem` only
+/// reads the first one.
+pub fn read_pem_certificates(path: &str) -> Result<Vec<Vec<u8>>> {
+    let pem = read(path)?;
+    let pem = String::from_utf8_lossy(&pem);
+
+    let starts: Vec<usize> = pem
+        .match_indices(PEM_CERTIFICATE_START)
+        .map(|(start, _)| start)
+        .collect();
+
+    let certificates = starts
+        .iter()
+        .enumerate()
+        .map(|(i, &start)| {
+            let end = starts.get(i + 1).copied().unwrap_or(pem.len());
+
+            pem.as_bytes()[start..end].to_vec()
+        })
+        .collect();
+
+    Ok(certificates)
+}
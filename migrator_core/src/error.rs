@@ -1,9 +1,11 @@
+use chrono::{Local, TimeZone};
 use std::fmt::Formatter;
 use std::sync::Arc;
 
 #[derive(Debug)]
 pub enum ErrorType {
-    InvalidMigrationPath(std::io::Error),
+    InvalidMigrationPath(std::io::Error, String),
+    FailedToReadMigrationDirectory(std::io::Error, String),
     InvalidDriver,
     InvalidDriverType(String),
     FailedToReadMigration(std::io::Error, String),
@@ -13,19 +15,68 @@ pub enum ErrorType {
     UnableToWriteConfig(confy::ConfyError),
     VersionCacheInvalidType(String),
     NonExistentMigrationVersions(String),
+    AmbiguousMigrationVersion(String),
     InvalidMigrationName(String),
+    DuplicateMigrationVersion(u64, Vec<String>),
+    OrphanedDownMigration(String),
     InvalidParameter,
     Clickhouse(Arc<clickhouse::error::Error>),
+    ClickhouseNative(Arc<clickhouse_rs::errors::Error>),
     RowNotFound(String),
     MissingConfigDefinition(String),
+    ChecksumMismatch {
+        name: String,
+        expected: String,
+        actual: String,
+    },
+    MissingMigrationFiles(Vec<String>),
+    EmptyMigration(String),
+    DatabaseLocked {
+        owner: String,
+        host: String,
+        expires_at: u64,
+    },
+    LockLost,
+    UnreplicatedTable {
+        table: String,
+        engine: String,
+    },
+    StatementFailed {
+        migration: String,
+        index: usize,
+        line: usize,
+        error: Box<ErrorType>,
+    },
+    MigrationFailed {
+        migration: String,
+        error: Box<ErrorType>,
+    },
+    UndefinedVariable(String),
+    UnknownEnvironment(String),
+    InvalidConfigValue {
+        field: String,
+        value: String,
+    },
+    Tls(native_tls::Error),
+    UnsupportedNativeTls(String),
+    FailedToReadCertificate(std::io::Error, String),
+    Io(std::io::Error),
 }
 
 impl std::fmt::Display for ErrorType {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         match *self {
-            ErrorType::InvalidMigrationPath(ref e) => write!(f, "{}", e),
-            ErrorType::NonExistentMigrationVersions(ref migration) => write!(f, "{}", migration),
+            ErrorType::InvalidMigrationPath(ref e, ref p) => {
+                write!(f, "invalid migration path {} - {}", p, e)
+            }
+            ErrorType::FailedToReadMigrationDirectory(ref e, ref p) => {
+                write!(f, "failed to read migration directory {} - {}", p, e)
+            }
+            ErrorType::NonExistentMigrationVersions(ref migration) => {
+                write!(f, "migration {} does not exist", migration)
+            }
             ErrorType::Clickhouse(ref e) => write!(f, "Clickhouse error: {}", e),
+            ErrorType::ClickhouseNative(ref e) => write!(f, "Clickhouse error: {}", e),
             ErrorType::InvalidDriverType(ref d) => write!(f, "invalid driver type {}", d),
             ErrorType::InvalidDriver => write!(f, "invalid driver provided"),
             ErrorType::FailedToReadMigration(ref e, ref m) => {
@@ -34,31 +85,138 @@ impl std::fmt::Display for ErrorType {
             ErrorType::FailedToWriteMigration(ref e, ref m) => {
                 write!(f, "failed to write migration {} - {}", m, e)
             }
-            ErrorType::FailedToExecuteMigration(ref m) => {
-                write!(f, "failed to execute migration {}", m)
+            ErrorType::FailedToExecuteMigration(ref e) => {
+                write!(f, "failed to execute migration - {}", e)
+            }
+            ErrorType::AmbiguousMigrationVersion(ref v) => {
+                write!(f, "{} matches more than one migration", v)
             }
             ErrorType::VersionCacheInvalidType(ref v) => write!(f, "invalid version {}", v),
             ErrorType::InvalidMigrationName(ref n) => {
-                write!(f, "invalid name: {}, must be x.sql or v1_x.sql", n)
+                write!(
+                    f,
+                    "invalid name: {}, must be <version>_<name>.up.sql or <version>_<name>.down.sql",
+                    n
+                )
+            }
+            ErrorType::DuplicateMigrationVersion(version, ref names) => write!(
+                f,
+                "version {} is used by more than one migration: {}",
+                version,
+                names.join(", ")
+            ),
+            ErrorType::OrphanedDownMigration(ref n) => {
+                write!(f, "{} has no matching up migration", n)
             }
             ErrorType::RowNotFound(ref e) => write!(f, "could not find row {}", e),
             ErrorType::UnableToWriteConfig(ref err) => {
                 write!(f, "Unable to write configuration: {}", err)
             }
             ErrorType::UnableToReadConfig(ref err) => {
-                write!(f, "Unable to read configuration: {:?}", err)
+                write!(f, "Unable to read configuration: {}", err)
             }
             ErrorType::MissingConfigDefinition(ref e) => {
-                write!(f, "Could not find config definition: {:?}", e)
+                write!(f, "Could not find config definition: {}", e)
+            }
+            ErrorType::InvalidParameter => write!(f, "invalid parameter provided"),
+            ErrorType::ChecksumMismatch {
+                ref name,
+                ref expected,
+                ref actual,
+            } => write!(
+                f,
+                "Migration directory is corrupt. {} has checksum {} but {} was applied",
+                name, actual, expected
+            ),
+            ErrorType::MissingMigrationFiles(ref names) => write!(
+                f,
+                "Migration directory is corrupt. Missing following files: {}",
+                names.join(", ")
+            ),
+            ErrorType::EmptyMigration(ref name) => write!(f, "{}. Empty migration file", name),
+            ErrorType::DatabaseLocked {
+                ref owner,
+                ref host,
+                expires_at,
+            } => write!(
+                f,
+                "Database is currently locked by {} on {} until {}, cannot run migrations",
+                owner,
+                host,
+                Local
+                    .timestamp_nanos(expires_at as i64)
+                    .format("%Y-%m-%d %H:%M:%S")
+            ),
+            ErrorType::LockLost => write!(f, "Migration lock was lost while running migrations"),
+            ErrorType::UnreplicatedTable {
+                ref table,
+                ref engine,
+            } => write!(
+                f,
+                "{} uses the {} engine, which isn't shared across the cluster. Rename or drop it on \
+                 every node so a replicated table can be created",
+                table, engine
+            ),
+            ErrorType::StatementFailed {
+                ref migration,
+                index,
+                line,
+                ref error,
+            } => write!(
+                f,
+                "statement {} of {} on line {} failed - {}",
+                index, migration, line, error
+            ),
+            ErrorType::MigrationFailed {
+                ref migration,
+                ref error,
+            } => write!(f, "migration {} failed - {}", migration, error),
+            ErrorType::UndefinedVariable(ref name) => {
+                write!(f, "variable ${{{}}} is not defined", name)
+            }
+            ErrorType::UnknownEnvironment(ref name) => {
+                write!(f, "environment {} is not defined in the config", name)
+            }
+            ErrorType::InvalidConfigValue {
+                ref field,
+                ref value,
+            } => write!(f, "invalid value {} for config field {}", value, field),
+            ErrorType::Tls(ref e) => write!(f, "TLS error: {}", e),
+            ErrorType::UnsupportedNativeTls(ref what) => {
+                write!(f, "the native driver doesn't support {}", what)
+            }
+            ErrorType::FailedToReadCertificate(ref e, ref p) => {
+                write!(f, "failed to read certificate {} - {}", p, e)
             }
-            _ => write!(f, "An unexpected error has occurred"),
+            ErrorType::Io(ref e) => write!(f, "IO error: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for ErrorType {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match *self {
+            ErrorType::InvalidMigrationPath(ref e, _)
+            | ErrorType::FailedToReadMigrationDirectory(ref e, _)
+            | ErrorType::FailedToReadMigration(ref e, _)
+            | ErrorType::FailedToWriteMigration(ref e, _)
+            | ErrorType::FailedToExecuteMigration(ref e)
+            | ErrorType::FailedToReadCertificate(ref e, _)
+            | ErrorType::Io(ref e) => Some(e),
+            ErrorType::UnableToReadConfig(ref e) | ErrorType::UnableToWriteConfig(ref e) => Some(e),
+            ErrorType::Clickhouse(ref e) => Some(e.as_ref()),
+            ErrorType::ClickhouseNative(ref e) => Some(e.as_ref()),
+            ErrorType::Tls(ref e) => Some(e),
+            ErrorType::StatementFailed { ref error, .. }
+            | ErrorType::MigrationFailed { ref error, .. } => Some(error.as_ref()),
+            _ => None,
         }
     }
 }
 
 impl From<std::io::Error> for ErrorType {
-    fn from(_: std::io::Error) -> Self {
-        todo!()
+    fn from(e: std::io::Error) -> Self {
+        ErrorType::Io(e)
     }
 }
 
@@ -67,3 +225,9 @@ impl From<clickhouse::error::Error> for ErrorType {
         ErrorType::Clickhouse(Arc::new(e))
     }
 }
+
+impl From<clickhouse_rs::errors::Error> for ErrorType {
+    fn from(e: clickhouse_rs::errors::Error) -> Self {
+        ErrorType::ClickhouseNative(Arc::new(e))
+    }
+}
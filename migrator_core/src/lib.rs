@@ -1,7 +1,15 @@
 pub mod clients;
 pub mod error;
 pub mod migration;
+pub mod plan;
 pub mod reader;
 pub mod report;
 pub mod result;
+pub mod statement;
+#[cfg(feature = "testing")]
+pub mod testing;
 pub mod util;
+pub mod variables;
+
+#[doc(hidden)]
+pub use migrator_macros::embed_files as __embed_files;
@@ -1,12 +1,105 @@
 use chrono::Local;
 use std::fmt::Formatter;
 
+use crate::clients::clickhouse::DatabaseClient;
+use crate::clients::history;
+use crate::error::ErrorType;
 use crate::result::Result;
+use crate::statement::split_statements;
 use crate::util::{calculate_hash, write_file};
+use async_trait::async_trait;
+use sha2::{Digest, Sha256};
 use std::hash::Hash;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 use tracing::*;
 
+/// A migration written in Rust, for changes that can't be expressed as SQL
+/// such as backfills. Registered with
+/// [`Driver::with_migration`](crate::clients::driver::Driver::with_migration),
+/// it runs in order with the migration files by version and is recorded in
+/// `clickhouse_migrations` as `<version>_<name>.up.rs`.
+///
+/// Changes to the code aren't detected once it has been applied.
+#[async_trait]
+pub trait Migration: Send + Sync {
+    /// Sorts with the timestamps of migration files, e.g. `20210101120000`.
+    fn version(&self) -> &str;
+
+    fn name(&self) -> &str;
+
+    async fn up(&self, client: &mut dyn DatabaseClient) -> Result<()>;
+
+    async fn down(&self, client: &mut dyn DatabaseClient) -> Result<()>;
+}
+
+#[async_trait]
+impl<M: Migration + ?Sized> Migration for Arc<M> {
+    fn version(&self) -> &str {
+        (**self).version()
+    }
+
+    fn name(&self) -> &str {
+        (**self).name()
+    }
+
+    async fn up(&self, client: &mut dyn DatabaseClient) -> Result<()> {
+        (**self).up(client).await
+    }
+
+    async fn down(&self, client: &mut dyn DatabaseClient) -> Result<()> {
+        (**self).down(client).await
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    Up,
+    Down,
+}
+
+/// The parts of a migration name, `<version>_<description>.<up|down>.sql`,
+/// e.g. `20210101120000_create-users.up.sql`. Rust migrations use `.rs`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MigrationName {
+    pub version: u64,
+    pub description: String,
+    pub direction: Direction,
+}
+
+impl std::str::FromStr for MigrationName {
+    type Err = ErrorType;
+
+    fn from_str(name: &str) -> std::result::Result<Self, Self::Err> {
+        let invalid = || ErrorType::InvalidMigrationName(name.to_string());
+
+        let stem = name
+            .strip_suffix(".sql")
+            .or_else(|| name.strip_suffix(".rs"))
+            .ok_or_else(invalid)?;
+
+        let (stem, direction) = match stem.strip_suffix(".up") {
+            Some(stem) => (stem, Direction::Up),
+            None => (
+                stem.strip_suffix(".down").ok_or_else(invalid)?,
+                Direction::Down,
+            ),
+        };
+
+        let (version, description) = stem.split_once('_').ok_or_else(invalid)?;
+
+        if description.is_empty() || !version.chars().all(|c| c.is_ascii_digit()) {
+            return Err(invalid());
+        }
+
+        Ok(MigrationName {
+            version: version.parse().map_err(|_| invalid())?,
+            description: description.to_string(),
+            direction,
+        })
+    }
+}
+
 #[derive(Debug, Clone, Hash)]
 pub struct MigrationFile {
     pub name: String,
@@ -20,23 +113,63 @@ impl std::fmt::Display for MigrationFile {
     }
 }
 
-impl Into<MigrationFile> for PathBuf {
-    fn into(self) -> MigrationFile {
-        let file_name = String::from(self.file_name().and_then(|name| name.to_str()).unwrap());
+impl From<PathBuf> for MigrationFile {
+    fn from(path: PathBuf) -> MigrationFile {
+        let content = std::fs::read_to_string(path.as_path()).unwrap_or_default();
+
+        MigrationFile::with_content(&path, content)
+    }
+}
+
+impl MigrationFile {
+    /// Reads a migration file, unlike `From<PathBuf>` this fails if the file
+    /// can't be read rather than treating it as empty.
+    pub fn read(path: PathBuf) -> Result<MigrationFile> {
+        let content = std::fs::read_to_string(path.as_path())
+            .map_err(|e| ErrorType::FailedToReadMigration(e, path.display().to_string()))?;
+
+        Ok(MigrationFile::with_content(&path, content))
+    }
+
+    fn with_content(path: &Path, content: String) -> MigrationFile {
+        let file_name = path.file_name().and_then(|name| name.to_str()).unwrap();
 
-        let content = std::fs::read_to_string(self.as_path()).unwrap_or("".to_string());
+        MigrationFile::with_name(file_name, content)
+    }
 
-        let rollback = file_name.contains(".down");
+    fn with_name(file_name: &str, content: String) -> MigrationFile {
+        let rollback = matches!(
+            file_name.parse::<MigrationName>(),
+            Ok(name) if name.direction == Direction::Down
+        );
 
         MigrationFile {
-            name: file_name,
+            name: file_name.to_string(),
             sql: content,
             rollback,
         }
     }
-}
 
-impl MigrationFile {
+    /// The up and down entries standing in for a Rust migration, they have no
+    /// SQL and are run by the driver instead.
+    pub fn code(migration: &dyn Migration) -> [MigrationFile; 2] {
+        let stem = format!("{}_{}", migration.version(), migration.name());
+
+        [
+            MigrationFile::with_name(&format!("{}.up.rs", stem), String::new()),
+            MigrationFile::with_name(&format!("{}.down.rs", stem), String::new()),
+        ]
+    }
+
+    /// Builds migrations from `(file name, sql)` pairs, as produced by
+    /// [`embed_migrations!`](crate::embed_migrations).
+    pub fn embedded(files: &[(&str, &str)]) -> Vec<MigrationFile> {
+        files
+            .iter()
+            .map(|(name, sql)| MigrationFile::with_name(name, sql.to_string()))
+            .collect()
+    }
+
     pub fn create(directory: String, name: String) -> Result<()> {
         let new_name = name.replace(" ", "-");
         let file_name = format!("{}_{}", Local::now().format("%Y%m%d%H%M%S"), &new_name);
@@ -44,31 +177,92 @@ impl MigrationFile {
         let up_path = PathBuf::from(format!("{}/{}.up.sql", &directory, &file_name));
         let down_path = PathBuf::from(format!("{}/{}.down.sql", &directory, &file_name));
 
-        write_file(up_path, &vec![])?;
-        write_file(down_path, &vec![])?;
+        write_file(up_path.clone(), &[])
+            .map_err(|e| ErrorType::FailedToWriteMigration(e, up_path.display().to_string()))?;
+        write_file(down_path.clone(), &[])
+            .map_err(|e| ErrorType::FailedToWriteMigration(e, down_path.display().to_string()))?;
 
         info!("Created new migration: {}", file_name);
 
         Ok(())
     }
 
-    pub fn checksum(&self) -> u64 {
+    pub fn parse_name(&self) -> Result<MigrationName> {
+        self.name.parse()
+    }
+
+    /// Whether this stands in for a Rust [`Migration`].
+    pub fn is_code(&self) -> bool {
+        self.name.ends_with(".rs")
+    }
+
+    /// Whether the file has no statements, only whitespace or comments.
+    pub fn is_empty(&self) -> bool {
+        !self.is_code() && split_statements(&self.sql).is_empty()
+    }
+
+    /// The name without the `.up.sql` or `.down.rs` style suffix.
+    pub fn stem(&self) -> &str {
+        stem(&self.name)
+    }
+
+    /// Hex encoded SHA-256 of the normalized SQL. Line endings and
+    /// surrounding whitespace don't affect the checksum.
+    pub fn checksum(&self) -> String {
+        let normalized: Vec<&str> = self.sql.trim().lines().map(|l| l.trim_end()).collect();
+
+        format!("{:x}", Sha256::digest(normalized.join("\n").as_bytes()))
+    }
+
+    /// The checksum written by older versions, which isn't stable across
+    /// Rust releases. Only used to verify rows before they are re-stamped.
+    pub fn legacy_checksum(&self) -> u64 {
         calculate_hash(self)
     }
 
     pub fn to_insert_sql(&self) -> String {
-        let timestamp = Local::now();
+        history::insert_sql(&self.name, &self.checksum())
+    }
+}
 
-        let ts_seconds = timestamp.timestamp();
-        let ts_nanos = timestamp.timestamp_subsec_nanos();
+/// Reads the migrations in a directory at compile time and expands to a
+/// `Vec<MigrationFile>`, for binaries that ship without their migrations
+/// directory. The path is relative to the crate's `Cargo.toml`, and a `.sql`
+/// file that isn't named `<version>_<name>.up.sql` or
+/// `<version>_<name>.down.sql` is a compile error.
+///
+/// ```ignore
+/// let migrations = migrator_core::embed_migrations!("./migrations");
+///
+/// driver.migrate(migrations).await?;
+/// ```
+///
+/// Changes to embedded files trigger a rebuild, files that are added or
+/// removed only show up after the crate is rebuilt for another reason.
+#[macro_export]
+macro_rules! embed_migrations {
+    ($dir:literal) => {
+        $crate::migration::MigrationFile::embedded($crate::__embed_files!($dir))
+    };
+}
 
-        let entry = ((ts_seconds as u64) * 1_000_000_000) + (ts_nanos as u64);
+fn stem(name: &str) -> &str {
+    let name = name.trim_end_matches(".sql").trim_end_matches(".rs");
 
-        format!(
-            "INSERT INTO clickhouse_migrations (*) VALUES ({}, '{}', '{}')",
-            entry,
-            self.name,
-            self.checksum()
-        )
-    }
+    name.strip_suffix(".up")
+        .or_else(|| name.strip_suffix(".down"))
+        .unwrap_or(name)
+}
+
+/// The name of the down migration that reverts the up migration `name`.
+pub fn down_name(name: &str) -> String {
+    let extension = if name.ends_with(".rs") { "rs" } else { "sql" };
+
+    format!("{}.down.{}", stem(name), extension)
+}
+
+/// Whether a checksum from the history table was written by the legacy
+/// `DefaultHasher` based implementation rather than SHA-256.
+pub fn is_legacy_checksum(checksum: &str) -> bool {
+    checksum.len() != 64 && checksum.parse::<u64>().is_ok()
 }
@@ -0,0 +1,50 @@
+use std::fmt::Formatter;
+
+#[derive(Debug, Clone)]
+pub struct PlanStep {
+    pub name: String,
+    pub queries: Vec<String>,
+}
+
+impl PlanStep {
+    pub fn new(name: &str, queries: Vec<String>) -> Self {
+        PlanStep {
+            name: name.to_string(),
+            queries,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Plan {
+    steps: Vec<PlanStep>,
+}
+
+impl Plan {
+    pub fn new(steps: Vec<PlanStep>) -> Self {
+        Plan { steps }
+    }
+
+    pub fn steps(&self) -> &[PlanStep] {
+        &self.steps
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.steps.is_empty()
+    }
+}
+
+impl std::fmt::Display for Plan {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        for step in &self.steps {
+            writeln!(f, "-- {}", step.name)?;
+
+            for query in &step.queries {
+                writeln!(f, "{};", query.trim().trim_end_matches(';'))?;
+            }
+
+            writeln!(f)?;
+        }
+        writeln!(f, "-- {} migrations", &self.steps.len())
+    }
+}
@@ -1,20 +1,167 @@
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
-use crate::migration::MigrationFile;
+use crate::clients::config::Config;
+use crate::error::ErrorType;
+use crate::migration::{Direction, MigrationFile, MigrationName};
 use crate::result::Result;
-use std::fs;
+use crate::util::standardise_path;
+use glob::{MatchOptions, Pattern};
+use std::collections::BTreeMap;
+use walkdir::WalkDir;
 
+/// Which migration files to read, by their path relative to the migrations
+/// directory. `*` doesn't match across directories, `**` does.
+#[derive(Debug, Clone, Default)]
+pub struct MigrationFilter {
+    include: Vec<Pattern>,
+    exclude: Vec<Pattern>,
+}
+
+impl MigrationFilter {
+    pub fn new(include: &[String], exclude: &[String]) -> Result<MigrationFilter> {
+        Ok(MigrationFilter {
+            include: patterns("migrations_include", include)?,
+            exclude: patterns("migrations_exclude", exclude)?,
+        })
+    }
+
+    pub fn from_config(config: &Config) -> Result<MigrationFilter> {
+        MigrationFilter::new(&config.migrations_include, &config.migrations_exclude)
+    }
+
+    /// Whether the file at `path`, relative to the migrations directory,
+    /// should be read.
+    pub fn matches(&self, path: &Path) -> bool {
+        let options = MatchOptions {
+            require_literal_separator: true,
+            ..MatchOptions::new()
+        };
+
+        let included = self.include.is_empty()
+            || self
+                .include
+                .iter()
+                .any(|p| p.matches_path_with(path, options));
+
+        included
+            && !self
+                .exclude
+                .iter()
+                .any(|p| p.matches_path_with(path, options))
+    }
+}
+
+fn patterns(field: &str, globs: &[String]) -> Result<Vec<Pattern>> {
+    globs
+        .iter()
+        .map(|glob| {
+            Pattern::new(glob).map_err(|_| ErrorType::InvalidConfigValue {
+                field: field.to_string(),
+                value: glob.clone(),
+            })
+        })
+        .collect()
+}
+
+/// Reads the migrations in the config's `migrations` directory, which is
+/// created if it doesn't exist, filtered by `migrations_include` and
+/// `migrations_exclude`.
+pub fn load_migrations(config: &Config) -> Result<Vec<MigrationFile>> {
+    let location = match &config.migrations {
+        Some(migrations) => migrations,
+        None => {
+            return Err(ErrorType::MissingConfigDefinition(
+                "Missing migrations definition".into(),
+            ))
+        }
+    };
+
+    let path = standardise_path(location)
+        .map_err(|e| ErrorType::InvalidMigrationPath(e, location.to_string()))?;
+
+    find_filtered_migration_files(path, &MigrationFilter::from_config(config)?)
+}
+
+/// Reads the `.sql` files in `path` and its subdirectories, sorted by
+/// version, see [`sort_migrations`].
 pub fn find_migration_files(path: PathBuf) -> Result<Vec<MigrationFile>> {
-    let file_paths = fs::read_dir(path)?
+    find_filtered_migration_files(path, &MigrationFilter::default())
+}
+
+pub fn find_filtered_migration_files(
+    path: PathBuf,
+    filter: &MigrationFilter,
+) -> Result<Vec<MigrationFile>> {
+    let mut migrations = Vec::new();
+
+    for entry in WalkDir::new(&path).follow_links(true).sort_by_file_name() {
+        let entry = entry.map_err(|e| {
+            ErrorType::FailedToReadMigrationDirectory(e.into(), path.display().to_string())
+        })?;
+
+        let is_sql = matches!(entry.file_name().to_str(), Some(name) if name.ends_with(".sql"));
+
+        if !entry.file_type().is_file() || !is_sql {
+            continue;
+        }
+
+        let relative = entry.path().strip_prefix(&path).unwrap_or(entry.path());
+
+        if filter.matches(relative) {
+            migrations.push(MigrationFile::read(entry.into_path())?);
+        }
+    }
+
+    sort_migrations(migrations)
+}
+
+/// Sorts migrations by version, with the down migration before the up one.
+/// Fails if a name can't be parsed, if a version is shared by migrations
+/// with different names or by files with the same name in different
+/// directories, or if a down migration has no up migration.
+pub fn sort_migrations(migrations: Vec<MigrationFile>) -> Result<Vec<MigrationFile>> {
+    let mut parsed: Vec<(MigrationName, MigrationFile)> = migrations
         .into_iter()
-        .filter_map(|e| e.ok())
-        .filter(move |entry| match entry.file_name().to_str() {
-            Some(file_name) if file_name.ends_with(".sql") => true,
-            Some(_file_name) => false,
-            None => false,
-        })
-        .map(|e| PathBuf::into(e.path()))
-        .collect();
+        .map(|m| Ok((m.parse_name()?, m)))
+        .collect::<Result<_>>()?;
+
+    parsed.sort_by(|(a, a_file), (b, b_file)| {
+        (a.version, &a_file.name).cmp(&(b.version, &b_file.name))
+    });
+
+    let mut versions: BTreeMap<u64, Vec<&(MigrationName, MigrationFile)>> = BTreeMap::new();
+    for migration in &parsed {
+        versions
+            .entry(migration.0.version)
+            .or_default()
+            .push(migration);
+    }
+
+    for (version, migrations) in versions {
+        let description = &migrations[0].0.description;
+
+        let ups = migrations
+            .iter()
+            .filter(|(name, _)| name.direction == Direction::Up)
+            .count();
+
+        if ups > 1
+            || migrations.len() - ups > 1
+            || migrations
+                .iter()
+                .any(|(name, _)| &name.description != description)
+        {
+            let names = migrations.iter().map(|(_, m)| m.name.clone()).collect();
+
+            return Err(ErrorType::DuplicateMigrationVersion(version, names));
+        }
+
+        if ups == 0 {
+            return Err(ErrorType::OrphanedDownMigration(
+                migrations[0].1.name.clone(),
+            ));
+        }
+    }
 
-    Ok(file_paths)
+    Ok(parsed.into_iter().map(|(_, m)| m).collect())
 }
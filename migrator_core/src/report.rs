@@ -1,4 +1,5 @@
 use crate::migration::MigrationFile;
+use chrono::{Local, TimeZone};
 use std::fmt::Formatter;
 
 #[derive(Debug, Clone)]
@@ -10,6 +11,15 @@ impl ExecutionReport {
     pub fn new(ran_migrations: Vec<MigrationFile>) -> Self {
         ExecutionReport { ran_migrations }
     }
+
+    /// The migrations that were run, in the order they ran.
+    pub fn migrations(&self) -> &[MigrationFile] {
+        &self.ran_migrations
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ran_migrations.is_empty()
+    }
 }
 
 impl std::fmt::Display for ExecutionReport {
@@ -20,3 +30,120 @@ impl std::fmt::Display for ExecutionReport {
         writeln!(f, "{} migrations", &self.ran_migrations.len())
     }
 }
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum MigrationState {
+    Applied {
+        applied_at: u64,
+    },
+    LegacyChecksum {
+        applied_at: u64,
+    },
+    Pending,
+    Empty,
+    Missing,
+    /// A Rust migration in the history that isn't registered with the
+    /// driver, such as one applied by the service that defines it.
+    AppliedExternally {
+        applied_at: u64,
+    },
+    ChecksumMismatch {
+        expected: String,
+        actual: String,
+    },
+}
+
+impl std::fmt::Display for MigrationState {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match *self {
+            MigrationState::Applied { .. } => write!(f, "applied"),
+            MigrationState::LegacyChecksum { .. } => write!(f, "legacy"),
+            MigrationState::Pending => write!(f, "pending"),
+            MigrationState::Empty => write!(f, "empty"),
+            MigrationState::Missing => write!(f, "missing"),
+            MigrationState::AppliedExternally { .. } => write!(f, "external"),
+            MigrationState::ChecksumMismatch { .. } => write!(f, "modified"),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct MigrationStatus {
+    pub name: String,
+    pub state: MigrationState,
+}
+
+impl MigrationStatus {
+    pub fn new(name: &str, state: MigrationState) -> Self {
+        MigrationStatus {
+            name: name.to_string(),
+            state,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct StatusReport {
+    migrations: Vec<MigrationStatus>,
+}
+
+impl StatusReport {
+    pub fn new(migrations: Vec<MigrationStatus>) -> Self {
+        StatusReport { migrations }
+    }
+
+    pub fn migrations(&self) -> &[MigrationStatus] {
+        &self.migrations
+    }
+
+    /// Whether any migration is missing on disk, modified or empty.
+    pub fn is_corrupt(&self) -> bool {
+        self.migrations.iter().any(|m| {
+            !matches!(
+                m.state,
+                MigrationState::Applied { .. }
+                    | MigrationState::LegacyChecksum { .. }
+                    | MigrationState::AppliedExternally { .. }
+                    | MigrationState::Pending
+            )
+        })
+    }
+}
+
+impl std::fmt::Display for StatusReport {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "{:<10} {:<19} MIGRATION", "STATE", "APPLIED AT")?;
+
+        for migration in &self.migrations {
+            let applied_at = match migration.state {
+                MigrationState::Applied { applied_at }
+                | MigrationState::LegacyChecksum { applied_at }
+                | MigrationState::AppliedExternally { applied_at } => Local
+                    .timestamp_nanos(applied_at as i64)
+                    .format("%Y-%m-%d %H:%M:%S")
+                    .to_string(),
+                _ => String::new(),
+            };
+
+            write!(
+                f,
+                "{:<10} {:<19} {}",
+                migration.state.to_string(),
+                applied_at,
+                migration.name
+            )?;
+
+            if let MigrationState::ChecksumMismatch {
+                ref expected,
+                ref actual,
+            } = migration.state
+            {
+                write!(f, " (checksum {} != {})", actual, expected)?;
+            }
+
+            writeln!(f)?;
+        }
+
+        writeln!(f, "{} migrations", &self.migrations.len())
+    }
+}
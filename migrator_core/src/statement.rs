@@ -0,0 +1,135 @@
+/// A single statement from a migration file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Statement {
+    pub sql: String,
+    /// Line the statement starts on, counting from 1.
+    pub line: usize,
+}
+
+/// Splits SQL into statements on `;`, ignoring any inside string literals,
+/// quoted identifiers, comments or `$$` heredocs. Comments are removed from
+/// the statements, and statements that only contain whitespace or comments
+/// are dropped.
+pub fn split_statements(sql: &str) -> Vec<Statement> {
+    let chars: Vec<char> = sql.chars().collect();
+
+    let mut statements = Vec::new();
+    let mut current = String::new();
+    let mut start_line: Option<usize> = None;
+    let mut line = 1;
+    let mut i = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+
+        match c {
+            ';' => {
+                push_statement(&mut statements, &mut current, &mut start_line);
+                i += 1;
+                continue;
+            }
+            // The newline ending a line comment is kept.
+            '-' if chars.get(i + 1) == Some(&'-') => {
+                i = find(&chars, i, "\n").unwrap_or(chars.len());
+                continue;
+            }
+            '#' => {
+                i = find(&chars, i, "\n").unwrap_or(chars.len());
+                continue;
+            }
+            // Replaced with a space so the tokens either side stay apart.
+            '/' if chars.get(i + 1) == Some(&'*') => {
+                let end = find(&chars, i + 2, "*/")
+                    .map(|end| end + 2)
+                    .unwrap_or(chars.len());
+                line += count_lines(&chars[i..end]);
+                current.push(' ');
+                i = end;
+                continue;
+            }
+            _ => {}
+        }
+
+        if !c.is_whitespace() && start_line.is_none() {
+            start_line = Some(line);
+        }
+
+        let end = match c {
+            '\'' | '"' | '`' => quoted_end(&chars, i, c),
+            '$' => heredoc_end(&chars, i).unwrap_or(i + 1),
+            _ => i + 1,
+        };
+
+        line += count_lines(&chars[i..end]);
+        current.extend(&chars[i..end]);
+        i = end;
+    }
+
+    push_statement(&mut statements, &mut current, &mut start_line);
+
+    statements
+}
+
+fn push_statement(
+    statements: &mut Vec<Statement>,
+    current: &mut String,
+    start_line: &mut Option<usize>,
+) {
+    if let Some(line) = start_line.take() {
+        statements.push(Statement {
+            sql: current.trim().to_string(),
+            line,
+        });
+    }
+
+    current.clear();
+}
+
+// Returns the index after the closing quote, quotes can be escaped with a
+// backslash or by doubling them.
+fn quoted_end(chars: &[char], start: usize, quote: char) -> usize {
+    let mut i = start + 1;
+
+    while i < chars.len() {
+        match chars[i] {
+            '\\' => i += 2,
+            c if c == quote && chars.get(i + 1) == Some(&quote) => i += 2,
+            c if c == quote => return i + 1,
+            _ => i += 1,
+        }
+    }
+
+    chars.len()
+}
+
+// Returns the index after the closing tag of a `$tag$ ... $tag$` heredoc, or
+// `None` if the `$` doesn't start one.
+fn heredoc_end(chars: &[char], start: usize) -> Option<usize> {
+    let tag_end = (start + 1..chars.len()).find(|&i| !is_tag_char(chars[i]))?;
+
+    if chars[tag_end] != '$' {
+        return None;
+    }
+
+    let tag: String = chars[start..=tag_end].iter().collect();
+
+    let end = find(chars, tag_end + 1, &tag)
+        .map(|end| end + tag.chars().count())
+        .unwrap_or(chars.len());
+
+    Some(end)
+}
+
+fn is_tag_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+fn find(chars: &[char], from: usize, needle: &str) -> Option<usize> {
+    let needle: Vec<char> = needle.chars().collect();
+
+    (from..chars.len()).find(|&i| chars[i..].starts_with(&needle))
+}
+
+fn count_lines(chars: &[char]) -> usize {
+    chars.iter().filter(|&&c| c == '\n').count()
+}
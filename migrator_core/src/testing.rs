@@ -0,0 +1,366 @@
+//! An in-memory stand-in for ClickHouse, for testing code built on
+//! [`Driver`](crate::clients::driver::Driver) without a server.
+//!
+//! ```ignore
+//! let client = FakeClient::new();
+//! let mut driver = Driver::new(Box::new(client.clone()));
+//!
+//! driver.migrate(migrations).await?;
+//!
+//! assert_eq!(client.migrations().len(), 2);
+//! ```
+
+use crate::clients::clickhouse::{LockRow, MigrationsRow, TableRow};
+use crate::clients::history::BACKUP_TABLE;
+use crate::clients::lock::SELECT_ACTIVE_LOCKS_QUERY;
+use crate::clients::traits::{RowFetcher, Transaction};
+use crate::clients::SELECT_TABLES_QUERY;
+use crate::error::ErrorType;
+use crate::result::Result;
+use async_trait::async_trait;
+use regex::Regex;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const QUOTED: &str = r"'((?:[^'\\]|\\.)*)'";
+
+/// A `DatabaseClient` that keeps `clickhouse_migrations` and
+/// `clickhouse_migration_locks` in memory and records every query it is
+/// sent. Tables are listed once they have been created. Clones share the
+/// same state, so a test can hand one to a `Driver` and inspect another.
+#[derive(Debug, Clone, Default)]
+pub struct FakeClient {
+    state: Arc<Mutex<State>>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    queries: Vec<String>,
+    tables: Vec<TableRow>,
+    migrations: Vec<MigrationsRow>,
+    backup: Vec<MigrationsRow>,
+    locks: Vec<FakeLock>,
+    fail_at: Option<usize>,
+    fail_on: Option<String>,
+}
+
+// The latest row for an owner in the lock table.
+#[derive(Debug, Clone)]
+struct FakeLock {
+    row: LockRow,
+    released: bool,
+}
+
+impl FakeClient {
+    pub fn new() -> FakeClient {
+        FakeClient::default()
+    }
+
+    /// Every query sent so far, including reads, in order.
+    pub fn queries(&self) -> Vec<String> {
+        self.state().queries.clone()
+    }
+
+    /// The tables that have been created, with their engines.
+    pub fn tables(&self) -> Vec<TableRow> {
+        self.state().tables.clone()
+    }
+
+    /// Adds a table, as if it had been created by an earlier version.
+    pub fn table(&self, name: &str, engine: &str) {
+        self.state().create(name, engine);
+    }
+
+    /// The applied migrations in `clickhouse_migrations`.
+    pub fn migrations(&self) -> Vec<MigrationsRow> {
+        self.state().migrations.clone()
+    }
+
+    /// Adds a row to `clickhouse_migrations`, as if the migration had been
+    /// applied at `timestamp`.
+    pub fn insert_migration(&self, row: MigrationsRow) {
+        self.state().migrations.push(row);
+    }
+
+    /// Claims the lock for another owner until `ttl` from now. A zero TTL
+    /// leaves an expired claim behind.
+    pub fn lock(&self, owner: &str, host: &str, ttl: Duration) {
+        let now = now();
+
+        self.state().claim(LockRow {
+            owner: owner.to_string(),
+            host: host.to_string(),
+            acquired_at: now,
+            expires_at: now + ttl.as_nanos() as u64,
+        });
+    }
+
+    /// Claims that are neither released nor expired, the earliest first.
+    pub fn active_locks(&self) -> Vec<LockRow> {
+        self.state().active_locks()
+    }
+
+    /// Fails the `n`th query sent from now on, counting from 1.
+    pub fn fail_at(&self, n: usize) {
+        let mut state = self.state();
+        state.fail_at = Some(state.queries.len() + n);
+    }
+
+    /// Fails every query containing `pattern`.
+    pub fn fail_on(&self, pattern: &str) {
+        self.state().fail_on = Some(pattern.to_string());
+    }
+
+    fn state(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn run(&self, query: &str) -> Result<()> {
+        let mut state = self.state();
+
+        state.queries.push(query.to_string());
+
+        let fails = state.fail_at == Some(state.queries.len())
+            || matches!(&state.fail_on, Some(pattern) if query.contains(pattern.as_str()));
+
+        if fails {
+            return Err(ErrorType::Io(std::io::Error::other(format!(
+                "injected failure for query {}",
+                state.queries.len()
+            ))));
+        }
+
+        state.apply(query);
+
+        Ok(())
+    }
+}
+
+impl State {
+    fn apply(&mut self, query: &str) {
+        let query = query.trim();
+
+        let create = Regex::new(r"(?s)^CREATE TABLE IF NOT EXISTS (\w+).*engine=(\w+)").unwrap();
+
+        if let Some(values) = create.captures(query) {
+            self.create(&values[1], &values[2]);
+        } else if query.starts_with("RENAME TABLE clickhouse_migrations TO ") {
+            self.backup = std::mem::take(&mut self.migrations);
+            self.tables.retain(|t| t.name != BACKUP_TABLE);
+            self.tables
+                .iter_mut()
+                .filter(|t| t.name == "clickhouse_migrations")
+                .for_each(|t| t.name = BACKUP_TABLE.to_string());
+        } else if query.starts_with("INSERT INTO clickhouse_migrations ") {
+            self.apply_history(query);
+        } else if query.starts_with("INSERT INTO clickhouse_migration_locks ") {
+            self.apply_lock(query);
+        }
+    }
+
+    fn create(&mut self, name: &str, engine: &str) {
+        if !self.tables.iter().any(|t| t.name == name) {
+            self.tables.push(TableRow {
+                name: name.to_string(),
+                engine: engine.to_string(),
+            });
+        }
+    }
+
+    // Applies, rollbacks, restamps and restores are the queries in
+    // `clients::history`.
+    fn apply_history(&mut self, query: &str) {
+        if query.contains(&format!("FROM {}", BACKUP_TABLE)) {
+            if self.migrations.is_empty() {
+                self.migrations = self.backup.clone();
+            }
+            return;
+        }
+
+        let insert =
+            Regex::new(&format!(r"SELECT now64\(9\), {}, {}, 0,", QUOTED, QUOTED)).unwrap();
+
+        if let Some(values) = insert.captures(query) {
+            let name = unescape(&values[1]);
+            let timestamp = self.migrations.iter().map(|m| m.timestamp + 1).max();
+
+            self.migrations.retain(|m| m.name != name);
+            self.migrations.push(MigrationsRow {
+                timestamp: now().max(timestamp.unwrap_or_default()),
+                name,
+                checksum: unescape(&values[2]),
+            });
+            return;
+        }
+
+        let name = Regex::new(&format!(r"WHERE name = {}", QUOTED)).unwrap();
+        let name = match name.captures(query) {
+            Some(values) => unescape(&values[1]),
+            None => return,
+        };
+
+        let restamp = Regex::new(&format!(r"SELECT timestamp, name, {}, 0,", QUOTED)).unwrap();
+
+        if let Some(values) = restamp.captures(query) {
+            self.migrations
+                .iter_mut()
+                .filter(|m| m.name == name)
+                .for_each(|m| m.checksum = unescape(&values[1]));
+        } else if query.contains("SELECT timestamp, name, checksum, 1,") {
+            self.migrations.retain(|m| m.name != name);
+        }
+    }
+
+    // Claims, heartbeats and releases are the queries built by `Lock`.
+    fn apply_lock(&mut self, query: &str) {
+        let ttl = Regex::new(r"toIntervalSecond\((\d+)\)")
+            .unwrap()
+            .captures(query)
+            .and_then(|c| c[1].parse::<u64>().ok())
+            .unwrap_or_default();
+        let expires_at = now() + ttl * 1_000_000_000;
+
+        let claim = Regex::new(&format!(r"SELECT {}, {}, now64\(9\)", QUOTED, QUOTED)).unwrap();
+
+        if let Some(values) = claim.captures(query) {
+            return self.claim(LockRow {
+                owner: unescape(&values[1]),
+                host: unescape(&values[2]),
+                acquired_at: now(),
+                expires_at,
+            });
+        }
+
+        let owner = Regex::new(&format!(r"WHERE owner = {}", QUOTED)).unwrap();
+        let owner = match owner.captures(query) {
+            Some(values) => unescape(&values[1]),
+            None => return,
+        };
+
+        let lock = match self
+            .locks
+            .iter_mut()
+            .find(|lock| lock.row.owner == owner && !lock.released)
+        {
+            Some(lock) => lock,
+            None => return,
+        };
+
+        if query.contains("expires_at, 1,") {
+            lock.released = true;
+        } else {
+            lock.row.expires_at = expires_at;
+        }
+    }
+
+    fn claim(&mut self, row: LockRow) {
+        self.locks.retain(|lock| lock.row.owner != row.owner);
+        self.locks.push(FakeLock {
+            row,
+            released: false,
+        });
+    }
+
+    fn active_locks(&self) -> Vec<LockRow> {
+        let now = now();
+
+        let mut active: Vec<LockRow> = self
+            .locks
+            .iter()
+            .filter(|lock| !lock.released && lock.row.expires_at > now)
+            .map(|lock| lock.row.clone())
+            .collect();
+
+        active.sort_by(|a, b| (a.acquired_at, &a.owner).cmp(&(b.acquired_at, &b.owner)));
+
+        active
+    }
+}
+
+#[async_trait]
+impl Transaction for FakeClient {
+    async fn execute_many(&mut self, queries: &[&str]) -> Result<()> {
+        for query in queries {
+            self.run(query)?;
+        }
+        Ok(())
+    }
+
+    async fn execute_query(&mut self, query: &str) -> Result<()> {
+        self.run(query)
+    }
+}
+
+#[async_trait]
+impl RowFetcher<LockRow> for FakeClient {
+    async fn fetch_one(&mut self, query: &str) -> Result<LockRow> {
+        let rows: Vec<LockRow> = self.fetch_many(query).await?;
+
+        rows.into_iter()
+            .next()
+            .ok_or_else(|| ErrorType::RowNotFound(query.to_string()))
+    }
+
+    async fn fetch_many(&mut self, query: &str) -> Result<Vec<LockRow>> {
+        self.run(query)?;
+
+        if !query.starts_with(SELECT_ACTIVE_LOCKS_QUERY) {
+            return Ok(vec![]);
+        }
+
+        Ok(self.state().active_locks())
+    }
+}
+
+#[async_trait]
+impl RowFetcher<TableRow> for FakeClient {
+    async fn fetch_one(&mut self, query: &str) -> Result<TableRow> {
+        let rows: Vec<TableRow> = self.fetch_many(query).await?;
+
+        rows.into_iter()
+            .next()
+            .ok_or_else(|| ErrorType::RowNotFound(query.to_string()))
+    }
+
+    async fn fetch_many(&mut self, query: &str) -> Result<Vec<TableRow>> {
+        self.run(query)?;
+
+        if query != SELECT_TABLES_QUERY {
+            return Ok(vec![]);
+        }
+
+        Ok(self.state().tables.clone())
+    }
+}
+
+#[async_trait]
+impl RowFetcher<MigrationsRow> for FakeClient {
+    async fn fetch_one(&mut self, query: &str) -> Result<MigrationsRow> {
+        let rows: Vec<MigrationsRow> = self.fetch_many(query).await?;
+
+        rows.into_iter()
+            .next()
+            .ok_or_else(|| ErrorType::RowNotFound(query.to_string()))
+    }
+
+    async fn fetch_many(&mut self, query: &str) -> Result<Vec<MigrationsRow>> {
+        self.run(query)?;
+
+        if !query.contains("FROM clickhouse_migrations") {
+            return Ok(vec![]);
+        }
+
+        Ok(self.state().migrations.clone())
+    }
+}
+
+fn unescape(value: &str) -> String {
+    value.replace("\\'", "'").replace("\\\\", "\\")
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_nanos() as u64)
+        .unwrap_or_default()
+}
@@ -14,7 +14,7 @@ pub fn standardise_path(location: &str) -> IOResult<PathBuf> {
     }
 
     // Standardise the location path
-    Ok(location.canonicalize().unwrap())
+    location.canonicalize()
 }
 
 pub fn write_file(path: PathBuf, data: &[u8]) -> std::io::Result<()> {
@@ -40,3 +40,17 @@ pub fn calculate_hash<T: Hash>(t: &T) -> u64 {
     t.hash(&mut s);
     s.finish()
 }
+
+pub fn hostname() -> String {
+    std::env::var("HOSTNAME")
+        .ok()
+        .or_else(|| std::fs::read_to_string("/etc/hostname").ok())
+        .map(|host| host.trim().to_string())
+        .filter(|host| !host.is_empty())
+        .unwrap_or_else(|| "unknown".to_string())
+}
+
+/// Escapes a value for a single quoted ClickHouse string literal.
+pub fn escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('\'', "\\'")
+}
@@ -0,0 +1,88 @@
+use crate::error::ErrorType;
+use crate::result::Result;
+use std::collections::HashMap;
+
+/// Values substituted for `${name}` placeholders in migration SQL when it is
+/// run. `${env.NAME}` reads the environment variable `NAME` instead.
+///
+/// Placeholders are substituted everywhere, including in strings and
+/// heredocs. `$${name}` is left as a literal `${name}`.
+///
+/// Migration files are checksummed before substitution, so changing a value
+/// doesn't change the checksum.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Variables {
+    values: HashMap<String, String>,
+}
+
+impl Variables {
+    pub fn new() -> Variables {
+        Variables::default()
+    }
+
+    pub fn set(&mut self, name: &str, value: &str) {
+        self.values.insert(name.to_string(), value.to_string());
+    }
+
+    pub fn get(&self, name: &str) -> Option<&String> {
+        self.values.get(name)
+    }
+
+    pub fn extend(&mut self, values: &HashMap<String, String>) {
+        for (name, value) in values {
+            self.set(name, value);
+        }
+    }
+
+    /// Substitutes every placeholder, failing if one isn't defined.
+    pub fn render(&self, sql: &str) -> Result<String> {
+        let mut rendered = String::with_capacity(sql.len());
+        let mut rest = sql;
+
+        while let Some(start) = rest.find("${") {
+            let after = &rest[start + 2..];
+
+            if rest[..start].ends_with('$') {
+                rendered.push_str(&rest[..start - 1]);
+                rendered.push_str("${");
+                rest = after;
+                continue;
+            }
+
+            let end = match after.find('}') {
+                Some(end) => end,
+                None => break,
+            };
+
+            let name = &after[..end];
+
+            let value = match name.strip_prefix("env.") {
+                Some(var) => std::env::var(var).ok(),
+                None => self.values.get(name).cloned(),
+            };
+
+            match value {
+                Some(value) => {
+                    rendered.push_str(&rest[..start]);
+                    rendered.push_str(&value);
+                }
+                None => return Err(ErrorType::UndefinedVariable(name.to_string())),
+            }
+
+            rest = &after[end + 1..];
+        }
+
+        rendered.push_str(rest);
+
+        Ok(rendered)
+    }
+}
+
+/// Parses a `name=value` assignment, as given to `--var`. The value may
+/// contain `=`.
+pub fn parse_var(s: &str) -> std::result::Result<(String, String), String> {
+    match s.split_once('=') {
+        Some((name, value)) if !name.is_empty() => Ok((name.to_string(), value.to_string())),
+        _ => Err(format!("expected name=value, got {}", s)),
+    }
+}
@@ -0,0 +1,243 @@
+use async_trait::async_trait;
+use migrator_core::clients::clickhouse::{DatabaseClient, LockRow};
+use migrator_core::clients::lock::Lock;
+use migrator_core::error::ErrorType;
+use migrator_core::migration::Migration;
+use migrator_core::report::MigrationState;
+use migrator_core::result::Result;
+use migrator_core::testing::FakeClient;
+use std::time::Duration;
+
+mod common;
+
+use common::{applied, driver, migration, migrations};
+
+struct Backfill;
+
+#[async_trait]
+impl Migration for Backfill {
+    fn version(&self) -> &str {
+        "20240101120000"
+    }
+
+    fn name(&self) -> &str {
+        "backfill_users"
+    }
+
+    async fn up(&self, client: &mut dyn DatabaseClient) -> Result<()> {
+        for page in 0..2 {
+            client
+                .execute_query(&format!("INSERT INTO users VALUES ({})", page))
+                .await?;
+        }
+        Ok(())
+    }
+
+    async fn down(&self, client: &mut dyn DatabaseClient) -> Result<()> {
+        client.execute_query("TRUNCATE TABLE users").await
+    }
+}
+
+// Runs for longer than a third of the lock TTL without sending a query.
+struct SlowBackfill;
+
+#[async_trait]
+impl Migration for SlowBackfill {
+    fn version(&self) -> &str {
+        "20240101120000"
+    }
+
+    fn name(&self) -> &str {
+        "slow_backfill"
+    }
+
+    async fn up(&self, client: &mut dyn DatabaseClient) -> Result<()> {
+        tokio::time::sleep(Duration::from_millis(1100)).await;
+        client.execute_query("SELECT 1").await
+    }
+
+    async fn down(&self, _client: &mut dyn DatabaseClient) -> Result<()> {
+        Ok(())
+    }
+}
+
+// Releases the lock behind the driver's back, as `lock release` would from
+// another run, then waits for the next heartbeat.
+struct StealLock;
+
+#[async_trait]
+impl Migration for StealLock {
+    fn version(&self) -> &str {
+        "20240101120000"
+    }
+
+    fn name(&self) -> &str {
+        "steal_lock"
+    }
+
+    async fn up(&self, client: &mut dyn DatabaseClient) -> Result<()> {
+        let lock = Lock::new(Duration::from_secs(3));
+        let holders: Vec<LockRow> = client.fetch_many(&lock.select_active_sql()).await?;
+
+        for holder in holders {
+            client
+                .execute_query(&lock.release_owner_sql(&holder.owner))
+                .await?;
+        }
+
+        tokio::time::sleep(Duration::from_millis(1100)).await;
+        Ok(())
+    }
+
+    async fn down(&self, _client: &mut dyn DatabaseClient) -> Result<()> {
+        Ok(())
+    }
+}
+
+fn ran(client: &FakeClient) -> Vec<String> {
+    client
+        .queries()
+        .into_iter()
+        .filter(|q| !q.contains("clickhouse_migration"))
+        .collect()
+}
+
+#[tokio::test]
+async fn migrate_interleaves_code_migrations_by_version() {
+    let client = FakeClient::new();
+    let mut driver = driver(&client).with_migration(Backfill);
+
+    driver.migrate(migrations()).await.unwrap();
+
+    assert_eq!(
+        applied(&client),
+        vec![
+            "20240101000000_users.up.sql",
+            "20240101120000_backfill_users.up.rs",
+            "20240102000000_events.up.sql",
+        ]
+    );
+    assert_eq!(
+        ran(&client),
+        vec![
+            "CREATE TABLE users (id UInt64)",
+            "INSERT INTO users VALUES (0)",
+            "INSERT INTO users VALUES (1)",
+            "CREATE TABLE events (id UInt64)",
+            "CREATE TABLE events_daily (id UInt64)",
+        ]
+    );
+}
+
+#[tokio::test]
+async fn rollback_runs_the_code_migration_down() {
+    let client = FakeClient::new();
+    let mut driver = driver(&client).with_migration(Backfill);
+
+    driver.migrate(migrations()).await.unwrap();
+    driver.rollback_steps(migrations(), 2).await.unwrap();
+
+    assert_eq!(applied(&client), vec!["20240101000000_users.up.sql"]);
+    assert_eq!(
+        ran(&client)[5..],
+        [
+            "DROP TABLE events_daily",
+            "DROP TABLE events",
+            "TRUNCATE TABLE users"
+        ]
+    );
+}
+
+#[tokio::test]
+async fn failed_code_migration_stops_the_run() {
+    let client = FakeClient::new();
+    client.fail_on("INSERT INTO users VALUES (1)");
+
+    let mut driver = driver(&client).with_migration(Backfill);
+    let error = driver.migrate(migrations()).await.unwrap_err();
+
+    assert!(
+        matches!(error, ErrorType::MigrationFailed { ref migration, .. } if migration == "20240101120000_backfill_users.up.rs")
+    );
+    assert_eq!(applied(&client), vec!["20240101000000_users.up.sql"]);
+    assert!(client.active_locks().is_empty());
+}
+
+#[tokio::test]
+async fn unregistered_code_migrations_are_applied_externally() {
+    let client = FakeClient::new();
+    driver(&client)
+        .with_migration(Backfill)
+        .migrate(migrations())
+        .await
+        .unwrap();
+
+    let mut driver = driver(&client);
+    let report = driver.migrate(migrations()).await.unwrap();
+    let status = driver.status(migrations()).await.unwrap();
+
+    assert!(report.is_empty());
+    assert!(!status.is_corrupt());
+    assert!(matches!(
+        status.migrations()[1].state,
+        MigrationState::AppliedExternally { .. }
+    ));
+}
+
+#[tokio::test]
+async fn code_migrations_cant_share_a_version_with_a_file() {
+    let mut files = migrations();
+    files.push(migration(
+        "20240101120000_other.up.sql",
+        "CREATE TABLE other (id UInt64)",
+    ));
+
+    let client = FakeClient::new();
+    let mut driver = driver(&client).with_migration(Backfill);
+    let error = driver.migrate(files).await.unwrap_err();
+
+    assert!(matches!(
+        error,
+        ErrorType::DuplicateMigrationVersion(20240101120000, _)
+    ));
+    assert!(client.queries().is_empty());
+}
+
+#[tokio::test]
+async fn slow_migrations_keep_the_lock() {
+    let client = FakeClient::new();
+    let mut driver = driver(&client)
+        .with_lock_ttl(Duration::from_secs(3))
+        .with_migration(SlowBackfill);
+
+    driver.migrate(migrations()).await.unwrap();
+
+    let heartbeats = client
+        .queries()
+        .into_iter()
+        .filter(|q| q.contains("SELECT owner, host, acquired_at, now64(9) + toIntervalSecond(3)"))
+        .count();
+
+    assert!(heartbeats >= 1);
+    assert_eq!(applied(&client).len(), 3);
+}
+
+#[tokio::test]
+async fn losing_the_lock_stops_the_run() {
+    let client = FakeClient::new();
+    let mut driver = driver(&client)
+        .with_lock_ttl(Duration::from_secs(3))
+        .with_migration(StealLock);
+
+    let error = driver.migrate(migrations()).await.unwrap_err();
+
+    assert!(matches!(error, ErrorType::LockLost));
+    assert_eq!(
+        applied(&client),
+        vec![
+            "20240101000000_users.up.sql",
+            "20240101120000_steal_lock.up.rs"
+        ]
+    );
+    assert!(!ran(&client).contains(&"CREATE TABLE events (id UInt64)".to_string()));
+}
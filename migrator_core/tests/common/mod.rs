@@ -0,0 +1,40 @@
+use migrator_core::clients::driver::Driver;
+use migrator_core::migration::MigrationFile;
+use migrator_core::testing::FakeClient;
+use std::time::Duration;
+
+pub fn migration(name: &str, sql: &str) -> MigrationFile {
+    MigrationFile {
+        name: name.to_string(),
+        sql: sql.to_string(),
+        rollback: name.ends_with(".down.sql"),
+    }
+}
+
+pub fn migrations() -> Vec<MigrationFile> {
+    vec![
+        migration(
+            "20240101000000_users.up.sql",
+            "CREATE TABLE users (id UInt64)",
+        ),
+        migration("20240101000000_users.down.sql", "DROP TABLE users"),
+        migration(
+            "20240102000000_events.up.sql",
+            "CREATE TABLE events (id UInt64);\nCREATE TABLE events_daily (id UInt64);",
+        ),
+        migration(
+            "20240102000000_events.down.sql",
+            "DROP TABLE events_daily;\nDROP TABLE events;",
+        ),
+    ]
+}
+
+pub fn applied(client: &FakeClient) -> Vec<String> {
+    client.migrations().into_iter().map(|m| m.name).collect()
+}
+
+// A driver for the client that doesn't wait for concurrent claims when it
+// takes the lock, since there are none.
+pub fn driver(client: &FakeClient) -> Driver {
+    Driver::new(Box::new(client.clone())).with_lock_settle(Duration::ZERO)
+}
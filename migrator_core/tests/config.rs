@@ -0,0 +1,218 @@
+use migrator_core::clients::config::{Config, Environment, LayeredConfig, Source};
+use migrator_core::clients::driver::DriverType;
+use migrator_core::error::ErrorType;
+use std::path::{Path, PathBuf};
+
+// A config file that only this test writes to.
+fn config_file(name: &str, contents: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!(
+        "migrator-config-{}-{}.toml",
+        name,
+        std::process::id()
+    ));
+    std::fs::write(&path, contents).unwrap();
+    path
+}
+
+fn source(config: &LayeredConfig, field: &str) -> Source {
+    config
+        .fields()
+        .into_iter()
+        .find(|(name, _, _)| *name == field)
+        .map(|(_, _, source)| source)
+        .unwrap()
+}
+
+fn value(config: &LayeredConfig, field: &str) -> Option<String> {
+    config.config().get(field)
+}
+
+fn staging() -> Config {
+    let mut config = Config::new("http")
+        .unwrap()
+        .uri("http://localhost:8123")
+        .lock_ttl(&300)
+        .cluster("main")
+        .migrations_include("2024/**")
+        .variable("days", "30")
+        .variable("table", "events");
+
+    config.environments.insert(
+        "staging".to_string(),
+        Environment {
+            driver: Some(DriverType::NativeDriver),
+            uri: Some("tcp://staging:9000".to_string()),
+            migrations_include: Some(vec![]),
+            variables: vec![("days".to_string(), "7".to_string())]
+                .into_iter()
+                .collect(),
+            ..Environment::default()
+        },
+    );
+
+    config
+}
+
+#[test]
+fn environments_override_the_fields_they_set() {
+    let config = staging().environment("staging").unwrap();
+
+    assert_eq!(config.driver, DriverType::NativeDriver);
+    assert_eq!(config.uri.as_deref(), Some("tcp://staging:9000"));
+    assert!(config.migrations_include.is_empty());
+}
+
+#[test]
+fn environments_inherit_the_fields_they_dont_set() {
+    let config = staging().environment("staging").unwrap();
+
+    assert_eq!(config.lock_ttl, Some(300));
+    assert_eq!(config.cluster.as_deref(), Some("main"));
+    assert!(config.environments.is_empty());
+}
+
+#[test]
+fn environment_variables_are_merged_with_the_base() {
+    let config = staging().environment("staging").unwrap();
+
+    assert_eq!(config.variables.get("days").unwrap(), "7");
+    assert_eq!(config.variables.get("table").unwrap(), "events");
+}
+
+#[test]
+fn rejects_unknown_environments() {
+    let error = staging().environment("production").unwrap_err();
+
+    assert!(matches!(error, ErrorType::UnknownEnvironment(ref name) if name == "production"));
+}
+
+#[test]
+fn get_returns_what_set_accepts() {
+    let mut config = Config::default();
+
+    for driver in &["http", "native"] {
+        config.set("driver", driver).unwrap();
+        let value = config.get("driver").unwrap();
+
+        assert_eq!(&value, driver);
+        config.set("driver", &value).unwrap();
+    }
+
+    config.set("db_port", "9440").unwrap();
+    config.set("migrations_include", "a/**, b/**").unwrap();
+    config.set("tls_skip_verify", "true").unwrap();
+
+    assert_eq!(config.get("db_port").as_deref(), Some("9440"));
+    assert_eq!(
+        config.get("migrations_include").as_deref(),
+        Some("a/**,b/**")
+    );
+    assert_eq!(config.get("tls_skip_verify").as_deref(), Some("true"));
+}
+
+#[test]
+fn set_accepts_the_driver_names_in_the_file() {
+    let mut config = Config::default();
+
+    config.set("driver", "NativeDriver").unwrap();
+    assert_eq!(config.driver, DriverType::NativeDriver);
+
+    config.set("driver", "ClickHouseDriver").unwrap();
+    assert_eq!(config.driver, DriverType::ClickHouseDriver);
+
+    assert!(matches!(
+        config.set("driver", "grpc"),
+        Err(ErrorType::InvalidDriverType(_))
+    ));
+}
+
+#[test]
+fn set_rejects_invalid_values() {
+    let mut config = Config::default();
+
+    assert!(matches!(
+        config.set("db_port", "nine"),
+        Err(ErrorType::InvalidConfigValue { ref field, .. }) if field == "db_port"
+    ));
+    assert!(config.set("unknown", "1").is_err());
+}
+
+#[test]
+fn layers_override_the_file_in_order() {
+    let path = config_file(
+        "layers",
+        "driver = \"ClickHouseDriver\"\nuri = \"http://file:8123\"\nlock_ttl = 60\n\
+         cluster = \"file\"\n",
+    );
+
+    std::env::set_var("CLICKHOUSE_MIGRATE_LOCK_TTL", "120");
+    std::env::set_var("CLICKHOUSE_MIGRATE_CLUSTER", "env");
+
+    let config = LayeredConfig::load(&path)
+        .unwrap()
+        .env_vars()
+        .unwrap()
+        .set("cluster", "flag", Source::Flag("--cluster".to_string()))
+        .unwrap();
+
+    std::env::remove_var("CLICKHOUSE_MIGRATE_LOCK_TTL");
+    std::env::remove_var("CLICKHOUSE_MIGRATE_CLUSTER");
+    std::fs::remove_file(&path).unwrap();
+
+    assert_eq!(value(&config, "uri").as_deref(), Some("http://file:8123"));
+    assert_eq!(source(&config, "uri"), Source::File(path.clone()));
+
+    assert_eq!(value(&config, "lock_ttl").as_deref(), Some("120"));
+    assert_eq!(
+        source(&config, "lock_ttl"),
+        Source::Variable("CLICKHOUSE_MIGRATE_LOCK_TTL".to_string())
+    );
+
+    assert_eq!(value(&config, "cluster").as_deref(), Some("flag"));
+    assert_eq!(
+        source(&config, "cluster"),
+        Source::Flag("--cluster".to_string())
+    );
+
+    assert_eq!(value(&config, "db_host"), None);
+    assert_eq!(source(&config, "db_host"), Source::Default);
+}
+
+#[test]
+fn layers_record_the_environment_that_changed_a_field() {
+    let path = config_file(
+        "environment",
+        "driver = \"ClickHouseDriver\"\nuri = \"http://file:8123\"\nlock_ttl = 60\n\n\
+         [environments.staging]\nuri = \"http://staging:8123\"\nlock_ttl = 60\n",
+    );
+
+    let config = LayeredConfig::load(&path)
+        .unwrap()
+        .environment("staging")
+        .unwrap();
+
+    std::fs::remove_file(&path).unwrap();
+
+    assert_eq!(
+        value(&config, "uri").as_deref(),
+        Some("http://staging:8123")
+    );
+    assert_eq!(
+        source(&config, "uri"),
+        Source::Environment("staging".to_string())
+    );
+    // Setting a field to the value it already had doesn't change its source.
+    assert_eq!(source(&config, "lock_ttl"), Source::File(path.clone()));
+}
+
+#[test]
+fn a_missing_file_is_the_default_config() {
+    let config = LayeredConfig::load(Path::new("/nonexistent/clickhouse.toml")).unwrap();
+
+    assert_eq!(config.config(), &Config::default());
+    assert_eq!(value(&config, "driver").as_deref(), Some("http"));
+    assert!(config
+        .fields()
+        .iter()
+        .all(|(_, _, source)| *source == Source::Default));
+}
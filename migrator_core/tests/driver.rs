@@ -0,0 +1,605 @@
+use migrator_core::clients::clickhouse::MigrationsRow;
+use migrator_core::error::ErrorType;
+use migrator_core::migration::MigrationFile;
+use migrator_core::report::MigrationState;
+use migrator_core::testing::FakeClient;
+use std::time::Duration;
+
+mod common;
+
+use common::{applied, driver, migration, migrations};
+
+fn ran(client: &FakeClient, prefix: &str) -> Vec<String> {
+    client
+        .queries()
+        .into_iter()
+        .filter(|q| q.starts_with(prefix))
+        .collect()
+}
+
+#[tokio::test]
+async fn migrate_applies_pending_migrations_in_order() {
+    let client = FakeClient::new();
+    let mut driver = driver(&client);
+
+    let report = driver.migrate(migrations()).await.unwrap();
+
+    assert_eq!(
+        report.to_string().lines().count(),
+        3,
+        "report header and one line per migration"
+    );
+    assert_eq!(
+        applied(&client),
+        vec![
+            "20240101000000_users.up.sql",
+            "20240102000000_events.up.sql"
+        ]
+    );
+    assert_eq!(
+        ran(&client, "CREATE TABLE "),
+        vec![
+            "CREATE TABLE users (id UInt64)",
+            "CREATE TABLE events (id UInt64)",
+            "CREATE TABLE events_daily (id UInt64)",
+        ]
+    );
+    assert!(client.active_locks().is_empty());
+}
+
+#[tokio::test]
+async fn migrate_skips_applied_migrations() {
+    let client = FakeClient::new();
+    let files = migrations();
+
+    client.insert_migration(MigrationsRow {
+        timestamp: 1,
+        name: files[0].name.clone(),
+        checksum: files[0].checksum(),
+    });
+
+    let mut driver = driver(&client);
+    driver.migrate(files).await.unwrap();
+
+    assert_eq!(
+        applied(&client),
+        vec![
+            "20240101000000_users.up.sql",
+            "20240102000000_events.up.sql"
+        ]
+    );
+    assert!(ran(&client, "CREATE TABLE users").is_empty());
+}
+
+#[tokio::test]
+async fn migrate_stops_at_a_failed_statement_and_releases_the_lock() {
+    let client = FakeClient::new();
+    client.fail_on("CREATE TABLE events_daily");
+
+    let mut driver = driver(&client);
+    let error = driver.migrate(migrations()).await.unwrap_err();
+
+    match error {
+        ErrorType::StatementFailed {
+            migration,
+            index,
+            line,
+            ..
+        } => {
+            assert_eq!(migration, "20240102000000_events.up.sql");
+            assert_eq!(index, 2);
+            assert_eq!(line, 2);
+        }
+        e => panic!("unexpected error {:?}", e),
+    }
+
+    assert_eq!(applied(&client), vec!["20240101000000_users.up.sql"]);
+    assert!(client.active_locks().is_empty());
+}
+
+#[tokio::test]
+async fn migrate_fails_at_the_nth_query() {
+    let client = FakeClient::new();
+    client.fail_at(1);
+
+    let mut driver = driver(&client);
+
+    assert!(driver.migrate(migrations()).await.is_err());
+    assert!(applied(&client).is_empty());
+    assert_eq!(client.queries().len(), 1);
+}
+
+#[tokio::test]
+async fn migrate_fails_when_another_run_holds_the_lock() {
+    let client = FakeClient::new();
+    client.lock("other-run", "other-host", Duration::from_secs(60));
+
+    let mut driver = driver(&client);
+    let error = driver.migrate(migrations()).await.unwrap_err();
+
+    assert!(matches!(error, ErrorType::DatabaseLocked { ref owner, .. } if owner == "other-run"));
+    assert!(applied(&client).is_empty());
+    assert_eq!(client.active_locks().len(), 1);
+}
+
+#[tokio::test]
+async fn migrate_takes_over_an_expired_lock() {
+    let client = FakeClient::new();
+    client.lock("crashed-run", "other-host", Duration::from_secs(0));
+
+    let mut driver = driver(&client);
+    driver.migrate(migrations()).await.unwrap();
+
+    assert_eq!(applied(&client).len(), 2);
+}
+
+#[tokio::test]
+async fn migrate_rejects_modified_migrations() {
+    let client = FakeClient::new();
+    client.insert_migration(MigrationsRow {
+        timestamp: 1,
+        name: "20240101000000_users.up.sql".to_string(),
+        checksum: "0".repeat(64),
+    });
+
+    let mut driver = driver(&client);
+    let error = driver.migrate(migrations()).await.unwrap_err();
+
+    assert!(matches!(error, ErrorType::ChecksumMismatch { .. }));
+    assert!(ran(&client, "CREATE TABLE events").is_empty());
+}
+
+#[tokio::test]
+async fn rollback_reverts_the_latest_migration() {
+    let client = FakeClient::new();
+    let mut driver = driver(&client);
+
+    driver.migrate(migrations()).await.unwrap();
+    driver.rollback(migrations()).await.unwrap();
+
+    assert_eq!(applied(&client), vec!["20240101000000_users.up.sql"]);
+    assert_eq!(
+        ran(&client, "DROP TABLE "),
+        vec!["DROP TABLE events_daily", "DROP TABLE events"]
+    );
+    assert!(client.active_locks().is_empty());
+}
+
+#[tokio::test]
+async fn rollback_steps_reverts_most_recent_first() {
+    let client = FakeClient::new();
+    let mut driver = driver(&client);
+
+    driver.migrate(migrations()).await.unwrap();
+    driver.rollback_steps(migrations(), 2).await.unwrap();
+
+    assert!(applied(&client).is_empty());
+    assert_eq!(
+        ran(&client, "DROP TABLE "),
+        vec![
+            "DROP TABLE events_daily",
+            "DROP TABLE events",
+            "DROP TABLE users"
+        ]
+    );
+}
+
+// The shared migrations followed by a third one.
+fn three_migrations() -> Vec<MigrationFile> {
+    let mut files = migrations();
+    files.push(migration(
+        "20240103000000_sessions.up.sql",
+        "CREATE TABLE sessions (id UInt64)",
+    ));
+    files.push(migration(
+        "20240103000000_sessions.down.sql",
+        "DROP TABLE sessions",
+    ));
+    files
+}
+
+#[tokio::test]
+async fn migrate_steps_applies_the_oldest_pending_migrations() {
+    let client = FakeClient::new();
+    let mut driver = driver(&client);
+
+    let report = driver.migrate_steps(three_migrations(), 2).await.unwrap();
+    let status = driver.status(three_migrations()).await.unwrap();
+
+    assert_eq!(report.migrations().len(), 2);
+    assert_eq!(
+        applied(&client),
+        vec![
+            "20240101000000_users.up.sql",
+            "20240102000000_events.up.sql"
+        ]
+    );
+    assert!(ran(&client, "CREATE TABLE sessions").is_empty());
+    assert!(matches!(
+        status.migrations()[2].state,
+        MigrationState::Pending
+    ));
+}
+
+#[tokio::test]
+async fn migrate_to_applies_up_to_the_target() {
+    let client = FakeClient::new();
+    let mut driver = driver(&client);
+
+    driver
+        .migrate_to(three_migrations(), "20240102000000_events.up.sql")
+        .await
+        .unwrap();
+
+    assert_eq!(
+        applied(&client),
+        vec![
+            "20240101000000_users.up.sql",
+            "20240102000000_events.up.sql"
+        ]
+    );
+    assert!(ran(&client, "CREATE TABLE sessions").is_empty());
+}
+
+#[tokio::test]
+async fn migrate_to_reverts_back_to_the_target() {
+    let client = FakeClient::new();
+    let mut driver = driver(&client);
+
+    driver.migrate(three_migrations()).await.unwrap();
+    driver
+        .migrate_to(three_migrations(), "20240101000000_users")
+        .await
+        .unwrap();
+
+    assert_eq!(applied(&client), vec!["20240101000000_users.up.sql"]);
+    assert_eq!(
+        ran(&client, "DROP TABLE "),
+        vec![
+            "DROP TABLE sessions",
+            "DROP TABLE events_daily",
+            "DROP TABLE events"
+        ]
+    );
+}
+
+#[tokio::test]
+async fn migrate_to_matches_a_prefix_of_the_name() {
+    let client = FakeClient::new();
+    let mut driver = driver(&client);
+
+    driver
+        .migrate_to(three_migrations(), "20240102")
+        .await
+        .unwrap();
+
+    assert_eq!(
+        applied(&client),
+        vec![
+            "20240101000000_users.up.sql",
+            "20240102000000_events.up.sql"
+        ]
+    );
+}
+
+#[tokio::test]
+async fn migrate_to_rejects_an_ambiguous_target() {
+    let client = FakeClient::new();
+    let mut driver = driver(&client);
+
+    let error = driver
+        .migrate_to(three_migrations(), "202401")
+        .await
+        .unwrap_err();
+
+    assert!(matches!(error, ErrorType::AmbiguousMigrationVersion(ref v) if v == "202401"));
+    assert!(applied(&client).is_empty());
+}
+
+#[tokio::test]
+async fn migrate_to_leaves_newer_applied_migrations() {
+    let client = FakeClient::new();
+    let files = three_migrations();
+
+    client.insert_migration(MigrationsRow {
+        timestamp: 1,
+        name: files[4].name.clone(),
+        checksum: files[4].checksum(),
+    });
+
+    let mut driver = driver(&client);
+    driver
+        .migrate_to(files, "20240102000000_events")
+        .await
+        .unwrap();
+
+    assert_eq!(
+        applied(&client),
+        vec![
+            "20240103000000_sessions.up.sql",
+            "20240101000000_users.up.sql",
+            "20240102000000_events.up.sql"
+        ]
+    );
+    assert!(ran(&client, "DROP TABLE ").is_empty());
+}
+
+#[tokio::test]
+async fn rollback_keeps_history_when_the_down_migration_fails() {
+    let client = FakeClient::new();
+    let mut driver = driver(&client);
+
+    driver.migrate(migrations()).await.unwrap();
+
+    client.fail_on("DROP TABLE events_daily");
+    let error = driver.rollback(migrations()).await.unwrap_err();
+
+    assert!(matches!(error, ErrorType::StatementFailed { .. }));
+    assert_eq!(applied(&client).len(), 2);
+    assert!(client.active_locks().is_empty());
+}
+
+#[tokio::test]
+async fn baseline_records_migrations_without_running_them() {
+    let client = FakeClient::new();
+    let mut driver = driver(&client);
+
+    let report = driver
+        .baseline(migrations(), "20240101000000")
+        .await
+        .unwrap();
+
+    assert_eq!(report.migrations().len(), 1);
+    assert_eq!(applied(&client), vec!["20240101000000_users.up.sql"]);
+    assert!(ran(&client, "CREATE TABLE ").is_empty());
+    assert!(client.active_locks().is_empty());
+
+    driver.migrate(migrations()).await.unwrap();
+
+    assert_eq!(
+        ran(&client, "CREATE TABLE "),
+        vec![
+            "CREATE TABLE events (id UInt64)",
+            "CREATE TABLE events_daily (id UInt64)",
+        ]
+    );
+}
+
+#[tokio::test]
+async fn baseline_fails_for_an_unknown_version() {
+    let client = FakeClient::new();
+    let mut driver = driver(&client);
+
+    let error = driver.baseline(migrations(), "2023").await.unwrap_err();
+
+    assert!(matches!(error, ErrorType::NonExistentMigrationVersions(_)));
+    assert!(applied(&client).is_empty());
+}
+
+#[tokio::test]
+async fn migrate_records_names_with_quotes() {
+    let client = FakeClient::new();
+    let mut driver = driver(&client);
+
+    driver
+        .migrate(vec![migration(
+            "20240101000000_user's_table.up.sql",
+            "CREATE TABLE users (id UInt64)",
+        )])
+        .await
+        .unwrap();
+
+    assert_eq!(applied(&client), vec!["20240101000000_user's_table.up.sql"]);
+}
+
+#[tokio::test]
+async fn rollback_keeps_history_when_removing_the_row_fails() {
+    let client = FakeClient::new();
+    let mut driver = driver(&client);
+
+    driver.migrate(migrations()).await.unwrap();
+
+    client.fail_on("SELECT timestamp, name, checksum, 1,");
+    assert!(driver.rollback(migrations()).await.is_err());
+
+    let deletes: Vec<String> = client
+        .queries()
+        .into_iter()
+        .filter(|q| q.contains("SELECT timestamp, name, checksum, 1,"))
+        .collect();
+
+    // The down migration ran, then marking its row deleted failed and the
+    // rollback stopped there.
+    assert_eq!(deletes.len(), 1);
+    assert!(deletes[0].contains("20240102000000_events.up.sql"));
+    assert_eq!(
+        ran(&client, "DROP TABLE "),
+        vec!["DROP TABLE events_daily", "DROP TABLE events"]
+    );
+    assert_eq!(applied(&client).len(), 2);
+}
+
+#[tokio::test]
+async fn restamp_replaces_legacy_checksums() {
+    let client = FakeClient::new();
+    let files = migrations();
+
+    client.insert_migration(MigrationsRow {
+        timestamp: 1,
+        name: files[0].name.clone(),
+        checksum: files[0].legacy_checksum().to_string(),
+    });
+
+    let mut driver = driver(&client);
+    let report = driver.restamp(files.clone(), false).await.unwrap();
+
+    assert_eq!(report.migrations().len(), 1);
+    assert_eq!(client.migrations()[0].checksum, files[0].checksum());
+    assert_eq!(client.migrations()[0].timestamp, 1);
+}
+
+#[tokio::test]
+async fn prerequisite_upgrades_a_tinylog_history_table() {
+    let client = FakeClient::new();
+    let files = migrations();
+
+    client.table("clickhouse_migrations", "TinyLog");
+    client.insert_migration(MigrationsRow {
+        timestamp: 1,
+        name: files[0].name.clone(),
+        checksum: files[0].checksum(),
+    });
+
+    let mut driver = driver(&client);
+    driver.migrate(files).await.unwrap();
+
+    assert_eq!(
+        applied(&client),
+        vec![
+            "20240101000000_users.up.sql",
+            "20240102000000_events.up.sql"
+        ]
+    );
+    assert!(ran(&client, "CREATE TABLE users").is_empty());
+
+    let engines: Vec<(String, String)> = client
+        .tables()
+        .into_iter()
+        .map(|t| (t.name, t.engine))
+        .collect();
+
+    assert!(engines.contains(&(
+        "clickhouse_migrations_backup".to_string(),
+        "TinyLog".to_string()
+    )));
+    assert!(engines.contains(&(
+        "clickhouse_migrations".to_string(),
+        "ReplacingMergeTree".to_string()
+    )));
+}
+
+#[tokio::test]
+async fn cluster_tables_are_replicated_on_every_node() {
+    let client = FakeClient::new();
+    let mut driver = driver(&client).with_cluster("analytics");
+
+    driver.migrate(migrations()).await.unwrap();
+
+    let creates = ran(&client, "\nCREATE TABLE IF NOT EXISTS clickhouse_migration");
+
+    assert_eq!(creates.len(), 2);
+    assert!(creates.iter().all(|q| q.contains("ON CLUSTER 'analytics'")));
+    // Replica names only have to be unique within a shard, every node holds
+    // a full copy so the shard is part of the name.
+    assert!(creates.iter().all(|q| q.contains("'{shard}-{replica}'")));
+}
+
+#[tokio::test]
+async fn cluster_locks_are_claimed_with_a_quorum() {
+    let client = FakeClient::new();
+    let mut driver = driver(&client).with_cluster("analytics");
+
+    driver.migrate(migrations()).await.unwrap();
+
+    let claims = ran(&client, "INSERT INTO clickhouse_migration_locks");
+    let reads = ran(&client, "\nSELECT owner, host, acquired_at, expires_at");
+
+    assert!(!claims.is_empty());
+    assert!(claims.iter().all(|q| q.contains("insert_quorum = 'auto'")));
+    assert!(!reads.is_empty());
+    assert!(reads
+        .iter()
+        .all(|q| q.contains("select_sequential_consistency = 1")));
+    assert_eq!(applied(&client).len(), 2);
+}
+
+#[tokio::test]
+async fn prerequisite_rejects_node_local_tables_on_a_cluster() {
+    let client = FakeClient::new();
+    client.table("clickhouse_migration_locks", "ReplacingMergeTree");
+
+    let mut driver = driver(&client).with_cluster("analytics");
+    let error = driver.migrate(migrations()).await.unwrap_err();
+
+    assert!(matches!(
+        error,
+        ErrorType::UnreplicatedTable { ref table, ref engine }
+            if table == "clickhouse_migration_locks" && engine == "ReplacingMergeTree"
+    ));
+    assert!(ran(&client, "CREATE TABLE").is_empty());
+    assert!(client.active_locks().is_empty());
+}
+
+#[tokio::test]
+async fn plan_treats_a_fresh_database_as_empty_without_creating_tables() {
+    let client = FakeClient::new();
+    let mut driver = driver(&client);
+
+    let plan = driver.plan(migrations()).await.unwrap();
+
+    let steps: Vec<&str> = plan.steps().iter().map(|s| s.name.as_str()).collect();
+
+    assert_eq!(
+        steps,
+        vec![
+            "20240101000000_users.up.sql",
+            "20240102000000_events.up.sql"
+        ]
+    );
+    assert!(client.tables().is_empty());
+    assert!(ran(&client, "CREATE TABLE").is_empty());
+    assert!(driver
+        .plan_rollback(migrations(), 1)
+        .await
+        .unwrap()
+        .is_empty());
+}
+
+#[tokio::test]
+async fn plan_reads_a_tinylog_history_table_without_upgrading_it() {
+    let client = FakeClient::new();
+    let files = migrations();
+
+    client.table("clickhouse_migrations", "TinyLog");
+    client.insert_migration(MigrationsRow {
+        timestamp: 1,
+        name: files[0].name.clone(),
+        checksum: files[0].checksum(),
+    });
+
+    let mut driver = driver(&client);
+    let plan = driver.plan(files).await.unwrap();
+
+    assert_eq!(plan.steps().len(), 1);
+    assert_eq!(plan.steps()[0].name, "20240102000000_events.up.sql");
+    assert_eq!(client.tables().len(), 1);
+    assert!(ran(&client, "RENAME TABLE").is_empty());
+}
+
+#[tokio::test]
+async fn status_reads_a_tinylog_history_table_without_upgrading_it() {
+    let client = FakeClient::new();
+    let files = migrations();
+
+    client.table("clickhouse_migrations", "TinyLog");
+    client.insert_migration(MigrationsRow {
+        timestamp: 1,
+        name: files[0].name.clone(),
+        checksum: files[0].checksum(),
+    });
+
+    let mut driver = driver(&client);
+    let status = driver.status(files).await.unwrap();
+
+    let states: Vec<String> = status
+        .migrations()
+        .iter()
+        .map(|m| m.state.to_string())
+        .collect();
+
+    assert_eq!(states, vec!["applied", "pending"]);
+    assert_eq!(client.tables().len(), 1);
+    assert_eq!(client.tables()[0].name, "clickhouse_migrations");
+    assert!(ran(&client, "RENAME TABLE").is_empty());
+    assert!(ran(&client, "\nCREATE TABLE").is_empty());
+}
@@ -0,0 +1,37 @@
+use migrator_core::clients::driver::Driver;
+use migrator_core::embed_migrations;
+use migrator_core::testing::FakeClient;
+
+#[test]
+fn embeds_migrations_sorted_by_name() {
+    let migrations = embed_migrations!("tests/migrations");
+
+    let names: Vec<(&str, bool)> = migrations
+        .iter()
+        .map(|m| (m.name.as_str(), m.rollback))
+        .collect();
+
+    assert_eq!(
+        names,
+        vec![
+            ("20240101000000_users.down.sql", true),
+            ("20240101000000_users.up.sql", false),
+            ("20240102000000_events.down.sql", true),
+            ("20240102000000_events.up.sql", false),
+        ]
+    );
+    assert_eq!(migrations[1].sql, "CREATE TABLE users (id UInt64)\n");
+}
+
+#[tokio::test]
+async fn migrates_embedded_migrations() {
+    let client = FakeClient::new();
+    let mut driver = Driver::new(Box::new(client.clone()));
+
+    driver
+        .migrate(embed_migrations!("tests/migrations"))
+        .await
+        .unwrap();
+
+    assert_eq!(client.migrations().len(), 2);
+}
@@ -0,0 +1,205 @@
+use migrator_core::error::ErrorType;
+use migrator_core::migration::{Direction, MigrationName};
+use migrator_core::reader::{find_filtered_migration_files, find_migration_files, MigrationFilter};
+use std::path::PathBuf;
+
+// A fresh directory with a migration file for each of the given names.
+fn directory(test: &str, names: &[&str]) -> PathBuf {
+    let path =
+        std::env::temp_dir().join(format!("migrator-reader-{}-{}", test, std::process::id()));
+
+    let _ = std::fs::remove_dir_all(&path);
+    std::fs::create_dir_all(&path).unwrap();
+
+    for name in names {
+        let file = path.join(name);
+
+        std::fs::create_dir_all(file.parent().unwrap()).unwrap();
+        std::fs::write(file, "SELECT 1").unwrap();
+    }
+
+    path
+}
+
+fn names(path: PathBuf) -> Vec<String> {
+    find_migration_files(path)
+        .unwrap()
+        .into_iter()
+        .map(|m| m.name)
+        .collect()
+}
+
+#[test]
+fn parses_migration_names() {
+    let name: MigrationName = "20240101000000_create-users.down.sql".parse().unwrap();
+
+    assert_eq!(
+        name,
+        MigrationName {
+            version: 20240101000000,
+            description: "create-users".to_string(),
+            direction: Direction::Down,
+        }
+    );
+
+    for invalid in &[
+        "users.up.sql",
+        "v1_users.up.sql",
+        "1_users.sql",
+        "1_.up.sql",
+    ] {
+        assert!(matches!(
+            invalid.parse::<MigrationName>(),
+            Err(ErrorType::InvalidMigrationName(_))
+        ));
+    }
+}
+
+#[test]
+fn sorts_migrations_by_version() {
+    let path = directory(
+        "sorted",
+        &[
+            "10_events.up.sql",
+            "9_users.down.sql",
+            "9_users.up.sql",
+            "10_events.down.sql",
+            "README.md",
+        ],
+    );
+
+    assert_eq!(
+        names(path),
+        vec![
+            "9_users.down.sql",
+            "9_users.up.sql",
+            "10_events.down.sql",
+            "10_events.up.sql",
+        ]
+    );
+}
+
+#[test]
+fn reads_the_direction_from_the_suffix() {
+    let path = directory(
+        "direction",
+        &[
+            "20240101000000_x.download.up.sql",
+            "20240101000000_x.download.down.sql",
+        ],
+    );
+
+    let migrations: Vec<(String, bool)> = find_migration_files(path)
+        .unwrap()
+        .into_iter()
+        .map(|m| (m.name, m.rollback))
+        .collect();
+
+    assert_eq!(
+        migrations,
+        vec![
+            ("20240101000000_x.download.down.sql".to_string(), true),
+            ("20240101000000_x.download.up.sql".to_string(), false),
+        ]
+    );
+}
+
+#[test]
+fn rejects_invalid_names() {
+    let path = directory("invalid", &["1_users.up.sql", "seed.sql"]);
+
+    assert!(matches!(
+        find_migration_files(path),
+        Err(ErrorType::InvalidMigrationName(ref name)) if name == "seed.sql"
+    ));
+}
+
+#[test]
+fn rejects_duplicate_versions() {
+    let path = directory("duplicate", &["1_users.up.sql", "1_events.up.sql"]);
+
+    assert!(matches!(
+        find_migration_files(path),
+        Err(ErrorType::DuplicateMigrationVersion(1, ref names)) if names.len() == 2
+    ));
+}
+
+#[test]
+fn rejects_orphaned_down_migrations() {
+    let path = directory("orphaned", &["1_users.up.sql", "2_events.down.sql"]);
+
+    assert!(matches!(
+        find_migration_files(path),
+        Err(ErrorType::OrphanedDownMigration(ref name)) if name == "2_events.down.sql"
+    ));
+}
+
+#[test]
+fn reads_subdirectories_in_version_order() {
+    let path = directory(
+        "nested",
+        &[
+            "2024/q2/3_events.up.sql",
+            "2024/q1/2_users.up.sql",
+            "2024/q1/2_users.down.sql",
+            "1_init.up.sql",
+            "events/4_events_daily.up.sql",
+        ],
+    );
+
+    assert_eq!(
+        names(path),
+        vec![
+            "1_init.up.sql",
+            "2_users.down.sql",
+            "2_users.up.sql",
+            "3_events.up.sql",
+            "4_events_daily.up.sql",
+        ]
+    );
+}
+
+#[test]
+fn rejects_the_same_migration_in_two_directories() {
+    let path = directory("copied", &["a/1_users.up.sql", "b/1_users.up.sql"]);
+
+    assert!(matches!(
+        find_migration_files(path),
+        Err(ErrorType::DuplicateMigrationVersion(1, _))
+    ));
+}
+
+#[test]
+fn filters_by_include_and_exclude_globs() {
+    let path = directory(
+        "filtered",
+        &[
+            "1_init.up.sql",
+            "2024/2_users.up.sql",
+            "2024/drafts/3_events.up.sql",
+            "2025/4_events_daily.up.sql",
+        ],
+    );
+
+    let filter = MigrationFilter::new(
+        &["2024/**".to_string(), "*.sql".to_string()],
+        &["**/drafts/**".to_string()],
+    )
+    .unwrap();
+
+    let names: Vec<String> = find_filtered_migration_files(path, &filter)
+        .unwrap()
+        .into_iter()
+        .map(|m| m.name)
+        .collect();
+
+    assert_eq!(names, vec!["1_init.up.sql", "2_users.up.sql"]);
+}
+
+#[test]
+fn rejects_invalid_globs() {
+    assert!(matches!(
+        MigrationFilter::new(&["[".to_string()], &[]),
+        Err(ErrorType::InvalidConfigValue { ref field, .. }) if field == "migrations_include"
+    ));
+}
@@ -0,0 +1,139 @@
+use migrator_core::statement::{split_statements, Statement};
+
+fn sql(input: &str) -> Vec<String> {
+    split_statements(input)
+        .into_iter()
+        .map(|statement| statement.sql)
+        .collect()
+}
+
+#[test]
+fn splits_on_semicolons() {
+    assert_eq!(
+        sql("SELECT 1; SELECT 2;\n\nSELECT 3"),
+        vec!["SELECT 1", "SELECT 2", "SELECT 3"]
+    );
+}
+
+#[test]
+fn drops_empty_statements() {
+    assert_eq!(sql(";;\n ; SELECT 1;;"), vec!["SELECT 1"]);
+    assert!(sql("  \n\t").is_empty());
+}
+
+#[test]
+fn ignores_semicolons_in_strings() {
+    assert_eq!(
+        sql("SELECT 'a;b'; SELECT 2"),
+        vec!["SELECT 'a;b'", "SELECT 2"]
+    );
+}
+
+#[test]
+fn ignores_semicolons_after_escaped_quotes() {
+    assert_eq!(
+        sql("SELECT 'it''s;'; SELECT 'it\\'s;'; SELECT '\\\\'; SELECT 4"),
+        vec![
+            "SELECT 'it''s;'",
+            "SELECT 'it\\'s;'",
+            "SELECT '\\\\'",
+            "SELECT 4"
+        ]
+    );
+}
+
+#[test]
+fn ignores_semicolons_in_quoted_identifiers() {
+    assert_eq!(
+        sql("SELECT `a;b`, \"c;d\" FROM t; SELECT 2"),
+        vec!["SELECT `a;b`, \"c;d\" FROM t", "SELECT 2"]
+    );
+    assert_eq!(
+        sql("SELECT `a``;b`, \"c\"\";d\"; SELECT 2"),
+        vec!["SELECT `a``;b`, \"c\"\";d\"", "SELECT 2"]
+    );
+}
+
+#[test]
+fn removes_line_comments() {
+    assert_eq!(
+        sql("-- a ; b\nSELECT 1; -- c\nSELECT 2 -- d ; e"),
+        vec!["SELECT 1", "SELECT 2"]
+    );
+}
+
+#[test]
+fn removes_hash_comments() {
+    assert_eq!(sql("# a ; b\nSELECT 5"), vec!["SELECT 5"]);
+    assert_eq!(sql("SELECT 1 # a ; b\n, 2"), vec!["SELECT 1 \n, 2"]);
+}
+
+#[test]
+fn removes_block_comments() {
+    assert_eq!(
+        sql("/* a ; b */ SELECT 1; SELECT/* c;\nd */2"),
+        vec!["SELECT 1", "SELECT 2"]
+    );
+}
+
+#[test]
+fn drops_statements_with_only_comments() {
+    assert_eq!(
+        sql("SELECT 1;\n-- trailing ; comment\n/* and ; another */\n# one more"),
+        vec!["SELECT 1"]
+    );
+}
+
+#[test]
+fn keeps_comment_markers_in_strings() {
+    assert_eq!(
+        sql("SELECT '-- a', '/* b */', '# c'; SELECT 2"),
+        vec!["SELECT '-- a', '/* b */', '# c'", "SELECT 2"]
+    );
+}
+
+#[test]
+fn ignores_semicolons_in_heredocs() {
+    assert_eq!(
+        sql("SELECT $$a; -- b$$; SELECT $tag$c; $$ d;$tag$; SELECT 3"),
+        vec![
+            "SELECT $$a; -- b$$",
+            "SELECT $tag$c; $$ d;$tag$",
+            "SELECT 3"
+        ]
+    );
+}
+
+#[test]
+fn keeps_dollars_that_dont_start_heredocs() {
+    assert_eq!(sql("SELECT $a; SELECT 2"), vec!["SELECT $a", "SELECT 2"]);
+}
+
+#[test]
+fn records_the_line_each_statement_starts_on() {
+    let statements = split_statements(
+        "-- header\n\nSELECT 1;\n/* multi\nline */ SELECT\n2;\n\n# note\nSELECT 'a\nb'; SELECT 4",
+    );
+
+    assert_eq!(
+        statements,
+        vec![
+            Statement {
+                sql: "SELECT 1".to_string(),
+                line: 3,
+            },
+            Statement {
+                sql: "SELECT\n2".to_string(),
+                line: 5,
+            },
+            Statement {
+                sql: "SELECT 'a\nb'".to_string(),
+                line: 9,
+            },
+            Statement {
+                sql: "SELECT 4".to_string(),
+                line: 10,
+            },
+        ]
+    );
+}
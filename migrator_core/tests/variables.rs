@@ -0,0 +1,139 @@
+use migrator_core::clients::config::Config;
+use migrator_core::clients::driver::Driver;
+use migrator_core::error::ErrorType;
+use migrator_core::migration::MigrationFile;
+use migrator_core::testing::FakeClient;
+use migrator_core::variables::{parse_var, Variables};
+use std::time::Duration;
+
+fn variables(values: &[(&str, &str)]) -> Variables {
+    let mut variables = Variables::new();
+    for (name, value) in values {
+        variables.set(name, value);
+    }
+    variables
+}
+
+#[test]
+fn substitutes_placeholders() {
+    let variables = variables(&[("db", "analytics"), ("days", "30")]);
+
+    assert_eq!(
+        variables
+            .render("CREATE TABLE ${db}.events TTL d + INTERVAL ${days} DAY; ${db}")
+            .unwrap(),
+        "CREATE TABLE analytics.events TTL d + INTERVAL 30 DAY; analytics"
+    );
+}
+
+#[test]
+fn reads_environment_variables() {
+    std::env::set_var("MIGRATOR_VARIABLES_TEST", "from_env");
+
+    assert_eq!(
+        Variables::new()
+            .render("SELECT '${env.MIGRATOR_VARIABLES_TEST}'")
+            .unwrap(),
+        "SELECT 'from_env'"
+    );
+}
+
+#[test]
+fn fails_on_undefined_placeholders() {
+    let error = Variables::new().render("SELECT ${missing}").unwrap_err();
+
+    assert!(matches!(error, ErrorType::UndefinedVariable(ref name) if name == "missing"));
+}
+
+#[test]
+fn substitutes_inside_strings_and_heredocs() {
+    let variables = variables(&[("x", "1")]);
+
+    assert_eq!(
+        variables.render("SELECT '${x}', $$ ${x} $$").unwrap(),
+        "SELECT '1', $$ 1 $$"
+    );
+}
+
+#[test]
+fn escapes_placeholders_with_a_double_dollar() {
+    let variables = variables(&[("x", "1")]);
+
+    assert_eq!(
+        variables
+            .render("SELECT '$${x}', '$${undefined}', ${x}")
+            .unwrap(),
+        "SELECT '${x}', '${undefined}', 1"
+    );
+}
+
+#[test]
+fn leaves_unterminated_placeholders() {
+    assert_eq!(
+        Variables::new().render("SELECT '${x'").unwrap(),
+        "SELECT '${x'"
+    );
+}
+
+#[test]
+fn parses_var_assignments() {
+    assert_eq!(
+        parse_var("days=30").unwrap(),
+        ("days".to_string(), "30".to_string())
+    );
+    assert_eq!(
+        parse_var("filter=a=b").unwrap(),
+        ("filter".to_string(), "a=b".to_string())
+    );
+    assert_eq!(
+        parse_var("empty=").unwrap(),
+        ("empty".to_string(), "".to_string())
+    );
+    assert!(parse_var("days").is_err());
+    assert!(parse_var("=30").is_err());
+}
+
+#[test]
+fn config_variables_override_the_connection() {
+    let mut config = Config::default();
+    config.uri = Some("http://localhost:8123/?database=analytics".to_string());
+    config.cluster = Some("main".to_string());
+
+    let connection = config.connection().unwrap();
+    let defaults = config.variables_for(&connection);
+
+    assert_eq!(defaults.get("database").unwrap(), "analytics");
+    assert_eq!(defaults.get("cluster").unwrap(), "main");
+
+    config
+        .variables
+        .insert("database".to_string(), "staging".to_string());
+    config.variables.insert("days".to_string(), "7".to_string());
+
+    let variables = config.variables_for(&connection);
+
+    assert_eq!(variables.get("database").unwrap(), "staging");
+    assert_eq!(variables.get("cluster").unwrap(), "main");
+    assert_eq!(variables.get("days").unwrap(), "7");
+}
+
+#[tokio::test]
+async fn driver_variables_override_the_cluster() {
+    let client = FakeClient::new();
+    let mut driver = Driver::new(Box::new(client.clone()))
+        .with_lock_settle(Duration::ZERO)
+        .with_cluster("main")
+        .with_variable("cluster", "other");
+
+    let migration = MigrationFile {
+        name: "20240101000000_users.up.sql".to_string(),
+        sql: "CREATE TABLE users ON CLUSTER '${cluster}' (id UInt64)".to_string(),
+        rollback: false,
+    };
+
+    driver.migrate(vec![migration]).await.unwrap();
+
+    assert!(client
+        .queries()
+        .contains(&"CREATE TABLE users ON CLUSTER 'other' (id UInt64)".to_string()));
+}
@@ -0,0 +1,125 @@
+//! Compile time support for `migrator_core`, use the macros through
+//! `migrator_core::embed_migrations!` rather than from here.
+
+use proc_macro::TokenStream;
+use quote::quote;
+use std::path::{Path, PathBuf};
+use syn::{parse_macro_input, LitStr};
+
+/// Expands to `&[(name, sql)]` for the migration files in a directory and its
+/// subdirectories, relative to the crate's `Cargo.toml`. Fails to compile if the directory
+/// can't be read, a `.sql` file isn't named `<version>_<name>.up.sql` or
+/// `<version>_<name>.down.sql`, a version is used by more than one name or a
+/// down migration has no up migration.
+#[proc_macro]
+pub fn embed_files(input: TokenStream) -> TokenStream {
+    let dir = parse_macro_input!(input as LitStr);
+
+    match migration_files(&dir.value()) {
+        Ok(files) => {
+            let files = files.iter().map(|(name, path)| {
+                let path = path.display().to_string();
+                quote! { (#name, include_str!(#path)) }
+            });
+
+            quote! { &[#(#files),*] }.into()
+        }
+        Err(message) => syn::Error::new(dir.span(), message)
+            .to_compile_error()
+            .into(),
+    }
+}
+
+fn migration_files(dir: &str) -> Result<Vec<(String, PathBuf)>, String> {
+    let root = std::env::var("CARGO_MANIFEST_DIR").map_err(|e| e.to_string())?;
+    let path = Path::new(&root).join(dir);
+
+    let mut paths = Vec::new();
+    sql_files(&path, &mut paths)?;
+
+    let mut files = Vec::new();
+
+    for path in paths {
+        let name = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .unwrap_or_default()
+            .to_string();
+
+        let (version, description, up) = parse_name(&name).ok_or_else(|| {
+            format!(
+                "Invalid migration name {}, expected <version>_<name>.up.sql or <version>_<name>.down.sql",
+                name
+            )
+        })?;
+
+        files.push((version, description, up, name, path));
+    }
+
+    if files.is_empty() {
+        return Err(format!("No migrations found in {}", path.display()));
+    }
+
+    files.sort_by(|a, b| (a.0, &a.3, &a.4).cmp(&(b.0, &b.3, &b.4)));
+
+    // The same rules as `migrator_core::reader::sort_migrations`
+    for (version, description, _, name, path) in &files {
+        let same_version: Vec<_> = files.iter().filter(|f| f.0 == *version).collect();
+
+        if let Some(other) = same_version
+            .iter()
+            .find(|f| f.1 != *description || (f.3 == *name && f.4 != *path))
+        {
+            return Err(format!(
+                "Version {} is used by more than one migration: {}, {}",
+                version, name, other.3
+            ));
+        }
+
+        if same_version.iter().all(|f| !f.2) {
+            return Err(format!("{} has no matching up migration", name));
+        }
+    }
+
+    Ok(files
+        .into_iter()
+        .map(|(_, _, _, name, path)| (name, path))
+        .collect())
+}
+
+// Collects the `.sql` files in `dir` and its subdirectories.
+fn sql_files(dir: &Path, files: &mut Vec<PathBuf>) -> Result<(), String> {
+    let entries = std::fs::read_dir(dir)
+        .map_err(|e| format!("Failed to read migrations in {}: {}", dir.display(), e))?;
+
+    for entry in entries {
+        let path = entry.map_err(|e| e.to_string())?.path();
+
+        if path.is_dir() {
+            sql_files(&path, files)?;
+        } else if path.extension().is_some_and(|ext| ext == "sql") {
+            files.push(path);
+        }
+    }
+
+    Ok(())
+}
+
+// Splits `<version>_<description>.<up|down>.sql` into the version, the
+// description and whether it is an up migration.
+fn parse_name(name: &str) -> Option<(u64, String, bool)> {
+    let stem = name.strip_suffix(".sql")?;
+
+    let (stem, up) = match stem.strip_suffix(".up") {
+        Some(stem) => (stem, true),
+        None => (stem.strip_suffix(".down")?, false),
+    };
+
+    let (version, description) = stem.split_once('_')?;
+
+    if description.is_empty() || !version.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+
+    Some((version.parse().ok()?, description.to_string(), up))
+}